@@ -1,3 +1,6 @@
 fn main() {
-    tauri_build::build()
+    // tauri-build reads metadata emitted by the tauri crate, which the headless build leaves out.
+    if std::env::var_os("CARGO_FEATURE_DESKTOP").is_some() {
+        tauri_build::build()
+    }
 }
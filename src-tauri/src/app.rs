@@ -1,31 +1,130 @@
 use crate::credentials::CredentialStore;
 use crate::diagnostics;
+use crate::domain::{PrStatus, PullRequestErrorEvent, PullRequestKey, PullRequestStatusEvent};
 use crate::error::{AppError, AppResult};
-use crate::monitor::Monitor;
+use crate::github::GithubClients;
+use crate::monitor::{Monitor, MonitorSink};
 use crate::storage::Storage;
 use log::LevelFilter;
-use tauri::menu::{MenuBuilder, MenuItemBuilder};
+use tauri::menu::{CheckMenuItemBuilder, MenuBuilder, MenuItemBuilder};
 use tauri::plugin::TauriPlugin;
 use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
-use tauri::{window::Color, Manager, PhysicalPosition, PhysicalSize, Rect, Runtime, WebviewWindow};
+use tauri::{
+    window::Color, AppHandle, Emitter, Manager, PhysicalPosition, PhysicalSize, Rect, Runtime,
+    WebviewWindow, Wry,
+};
 use tauri_plugin_log::{RotationStrategy, Target, TargetKind, TimezoneStrategy};
+use tauri_plugin_notification::NotificationExt;
 
 pub struct AppState {
     pub credentials: CredentialStore,
+    pub github: GithubClients,
     pub storage: Storage,
     pub monitor: Monitor,
 }
 
 impl AppState {
     pub fn new(db_path: std::path::PathBuf) -> AppResult<Self> {
+        let storage = Storage::new(db_path)?;
+
         Ok(Self {
             credentials: CredentialStore::default(),
-            storage: Storage::new(db_path)?,
+            github: GithubClients::new(storage.clone()),
+            storage,
             monitor: Monitor::default(),
         })
     }
 }
 
+/// Forwards monitor side effects to the webview and desktop notifications.
+pub struct TauriSink {
+    app_handle: AppHandle<Wry>,
+}
+
+impl TauriSink {
+    pub fn new(app_handle: AppHandle<Wry>) -> Self {
+        Self { app_handle }
+    }
+
+    fn emit<S: serde::Serialize + Clone>(&self, event: &str, payload: S) -> AppResult<()> {
+        self.app_handle
+            .emit(event, payload)
+            .map_err(|err| AppError::Event(err.to_string()))
+    }
+
+    fn notify(&self, title: &str, body: &str) -> AppResult<()> {
+        self.app_handle
+            .notification()
+            .builder()
+            .title(title)
+            .body(body)
+            .show()
+            .map_err(|err| AppError::Notification(err.to_string()))
+    }
+}
+
+impl MonitorSink for TauriSink {
+    fn status_checked(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()> {
+        let payload = PullRequestStatusEvent {
+            key: key.clone(),
+            status,
+        };
+        self.emit("pr-status", &payload)
+    }
+
+    fn check_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()> {
+        let payload = PullRequestErrorEvent {
+            key: key.clone(),
+            error: error.to_owned(),
+        };
+        self.emit("pr-error", &payload)
+    }
+
+    fn pr_merged(&self, key: &PullRequestKey) -> AppResult<()> {
+        self.emit("pr-merged", key)
+    }
+
+    fn pr_closed(&self, key: &PullRequestKey) -> AppResult<()> {
+        self.emit("pr-closed", key)
+    }
+
+    fn pr_reopened(&self, key: &PullRequestKey) -> AppResult<()> {
+        self.emit("pr-reopened", key)
+    }
+
+    fn branch_updated(&self, key: &PullRequestKey) -> AppResult<()> {
+        self.emit("pr-branch-updated", key)
+    }
+
+    fn would_update_branch(&self, key: &PullRequestKey) -> AppResult<()> {
+        self.emit("pr-would-update-branch", key)?;
+        let title = format!("Dry run: PR #{} is behind", key.pr_number);
+        self.notify(&title, "Would update branch.")
+    }
+
+    fn update_failed(&self, _key: &PullRequestKey, error: &str) -> AppResult<()> {
+        self.notify("Failed to update PR", error)
+    }
+
+    fn needs_attention(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()> {
+        let status_str = match status {
+            PrStatus::Behind => "is behind",
+            PrStatus::Conflicts => "has conflicts",
+            PrStatus::Blocked => "is blocked",
+            _ => "has an unknown status",
+        };
+        let title = format!("PR Not Updated: #{}", key.pr_number);
+        let body = format!("PR {} - please check.", status_str);
+        self.notify(&title, &body)
+    }
+
+    fn reruns_exhausted(&self, key: &PullRequestKey, jobs: &str) -> AppResult<()> {
+        let title = format!("CI keeps failing: #{}", key.pr_number);
+        let body = format!("No re-runs left for {jobs}.");
+        self.notify(&title, &body)
+    }
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     diagnostics::install_panic_hook();
@@ -55,16 +154,31 @@ pub fn run() {
             log::info!("App initialized");
             log::info!("Log directory: {}", diagnostics::app_log_dir().display());
 
+            let dry_run_enabled =
+                tauri::async_runtime::block_on(app.state::<AppState>().storage.get_dry_run())?;
+            let dry_run = CheckMenuItemBuilder::new("Dry run")
+                .id("dry_run")
+                .checked(dry_run_enabled)
+                .build(app)?;
             let quit = MenuItemBuilder::new("Quit").id("quit").build(app)?;
-            let menu = MenuBuilder::new(app).items(&[&quit]).build()?;
+            let menu = MenuBuilder::new(app).items(&[&dry_run, &quit]).build()?;
 
             let mut tray_builder = TrayIconBuilder::new()
                 .menu(&menu)
                 .show_menu_on_left_click(false)
-                .on_menu_event(|app, event| {
-                    if event.id().as_ref() == "quit" {
-                        app.exit(0)
+                .on_menu_event(move |app, event| match event.id().as_ref() {
+                    "quit" => app.exit(0),
+                    "dry_run" => {
+                        let enabled = dry_run.is_checked().unwrap_or(false);
+                        let storage = app.state::<AppState>().storage.clone();
+                        tauri::async_runtime::spawn(async move {
+                            match storage.set_dry_run(enabled).await {
+                                Ok(()) => log::info!("Dry run set to {enabled}"),
+                                Err(err) => log::error!("Failed to save dry run setting: {err}"),
+                            }
+                        });
                     }
+                    _ => {}
                 })
                 .on_tray_icon_event(|tray, event| {
                     let app = tray.app_handle();
@@ -112,8 +226,38 @@ pub fn run() {
             crate::commands::get_refresh_time,
             crate::commands::set_refresh_time,
             crate::commands::delete_pr,
+            crate::commands::get_pr_history,
+            crate::commands::set_pr_mode,
+            crate::commands::set_pr_update_method,
+            crate::commands::set_pr_auto_merge,
+            crate::commands::get_repo_update_methods,
+            crate::commands::set_repo_update_method,
+            crate::commands::get_behind_threshold,
+            crate::commands::set_behind_threshold,
+            crate::commands::get_repo_behind_thresholds,
+            crate::commands::set_repo_behind_threshold,
+            crate::commands::get_ci_policy,
+            crate::commands::set_ci_policy,
+            crate::commands::get_default_mode,
+            crate::commands::set_default_mode,
+            crate::commands::export_audit_log,
             crate::commands::get_show_notification,
             crate::commands::set_show_notification,
+            crate::commands::get_auto_enqueue,
+            crate::commands::set_auto_enqueue,
+            crate::commands::get_reminder_hours,
+            crate::commands::set_reminder_hours,
+            crate::commands::get_max_updates_per_hour,
+            crate::commands::set_max_updates_per_hour,
+            crate::commands::get_update_spacing_minutes,
+            crate::commands::set_update_spacing_minutes,
+            crate::commands::get_rerun_budget,
+            crate::commands::set_rerun_budget,
+            crate::commands::get_rerun_job_patterns,
+            crate::commands::set_rerun_job_patterns,
+            crate::commands::get_max_concurrent_checks,
+            crate::commands::set_max_concurrent_checks,
+            crate::commands::get_rate_limit,
             crate::commands::get_theme,
             crate::commands::set_theme
         ])
@@ -0,0 +1,66 @@
+use crate::domain::AuditEntry;
+
+const CSV_HEADER: &str =
+    "at,owner,repo,pr_number,action,result,head_sha_before,head_sha_after,error";
+
+/// Renders the audit log as CSV, so it can be opened in a spreadsheet or shared as is.
+pub fn to_csv(entries: &[AuditEntry]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push('\n');
+
+    for entry in entries {
+        let fields = [
+            entry.at.to_rfc3339(),
+            entry.key.owner.clone(),
+            entry.key.repo.clone(),
+            entry.key.pr_number.to_string(),
+            entry.action.as_str().to_owned(),
+            entry.result.as_str().to_owned(),
+            entry.head_sha_before.clone(),
+            entry.head_sha_after.clone().unwrap_or_default(),
+            entry.error.clone().unwrap_or_default(),
+        ];
+        let row: Vec<_> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+
+    csv
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::{AuditAction, AuditResult, PullRequestKey};
+    use chrono::DateTime;
+
+    #[test]
+    fn audit_log_is_rendered_as_csv_with_quoted_errors() {
+        let entry = AuditEntry {
+            key: PullRequestKey::new("acme", "widgets", 7),
+            action: AuditAction::UpdateBranch,
+            head_sha_before: "abc123".to_owned(),
+            head_sha_after: None,
+            result: AuditResult::Failed,
+            error: Some("merge conflict, \"main\" moved".to_owned()),
+            at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+        };
+
+        let csv = to_csv(&[entry]);
+
+        assert_eq!(
+            csv,
+            "at,owner,repo,pr_number,action,result,head_sha_before,head_sha_after,error\n\
+             2023-11-14T22:13:20+00:00,acme,widgets,7,update_branch,failed,abc123,,\
+             \"merge conflict, \"\"main\"\" moved\"\n"
+        );
+    }
+}
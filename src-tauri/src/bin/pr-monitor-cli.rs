@@ -0,0 +1,3 @@
+fn main() {
+    pull_request_monitor_lib::cli::run()
+}
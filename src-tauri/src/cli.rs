@@ -0,0 +1,713 @@
+use crate::audit;
+use crate::credentials::CredentialStore;
+use crate::diagnostics;
+use crate::domain::{CiPolicy, MergeMethod, MonitorMode, PrStatus, PullRequestKey, UpdateMethod};
+use crate::error::{AppError, AppResult};
+use crate::github::{parse_github_pr_url, OctocrabProvider, PullRequestProvider};
+use crate::monitor::{check_pull_requests, set_auto_merge, Monitor, MonitorSink};
+use crate::storage::Storage;
+use log::{info, warn};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const USAGE: &str = "\
+Usage: pr-monitor-cli [--db <path>] <command>
+
+Commands:
+  add <url>                   Start watching a pull request
+  list                        List watched pull requests
+  remove <url>                Stop watching a pull request
+  mode <url> <mode>           Set auto_update, notify_only or silent for a pull request
+  update-method <url> <method> [--repo]
+                              Update the branch with merge or rebase, or default to
+                              follow the repository. --repo sets it for the whole repository
+  auto-merge <url> <method>   Keep GitHub auto-merge on with merge, squash or rebase,
+                              or off to turn it off
+  behind-threshold <commits> [--repo <url>]
+                              Update branches once this many commits behind. With --repo
+                              it applies to the PR's repository, 0 to follow the global one
+  ci-policy <policy>          Update branches always, not_while_pending or only_when_passing
+  auto-enqueue <on|off>       Add approved PRs with passing CI to their merge queue
+  update-budget <per-hour> <spacing-minutes>
+                              Limit branch updates per repository, 0 for no limit
+  rerun-jobs <per-pr> [<pattern>...]
+                              Re-run failed Actions jobs matching the patterns, where *
+                              matches anything, up to this many runs per PR. 0 is off
+  run [--interval <minutes>]  Check pull requests until interrupted
+  check-once                  Check pull requests a single time
+  audit                       Print every change made on GitHub as CSV
+
+The GitHub token is read from GITHUB_TOKEN, falling back to the token saved by the app.";
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    db_path: Option<PathBuf>,
+    command: Command,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Add {
+        url: String,
+    },
+    List,
+    Remove {
+        url: String,
+    },
+    Mode {
+        url: String,
+        mode: MonitorMode,
+    },
+    UpdateMethod {
+        url: String,
+        method: Option<UpdateMethod>,
+        repository: bool,
+    },
+    AutoMerge {
+        url: String,
+        method: Option<MergeMethod>,
+    },
+    BehindThreshold {
+        commits: u32,
+        repository_of: Option<String>,
+    },
+    CiPolicy {
+        policy: CiPolicy,
+    },
+    AutoEnqueue {
+        enabled: bool,
+    },
+    UpdateBudget {
+        per_hour: Option<u32>,
+        spacing_minutes: Option<u64>,
+    },
+    RerunJobs {
+        budget: Option<u32>,
+        patterns: Vec<String>,
+    },
+    Run {
+        interval_minutes: Option<u64>,
+    },
+    CheckOnce,
+    Audit,
+}
+
+/// Writes monitor side effects to the log, since there is no window to show them in.
+struct LogSink;
+
+impl MonitorSink for LogSink {
+    fn status_checked(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()> {
+        info!("{} is {status}", display_key(key));
+        Ok(())
+    }
+
+    fn check_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()> {
+        warn!("Failed to check {}: {error}", display_key(key));
+        Ok(())
+    }
+
+    fn pr_merged(&self, key: &PullRequestKey) -> AppResult<()> {
+        info!("{} was merged", display_key(key));
+        Ok(())
+    }
+
+    fn pr_closed(&self, key: &PullRequestKey) -> AppResult<()> {
+        info!("{} was closed without merging", display_key(key));
+        Ok(())
+    }
+
+    fn pr_reopened(&self, key: &PullRequestKey) -> AppResult<()> {
+        info!("{} was reopened", display_key(key));
+        Ok(())
+    }
+
+    fn branch_updated(&self, key: &PullRequestKey) -> AppResult<()> {
+        info!("{} branch updated", display_key(key));
+        Ok(())
+    }
+
+    fn would_update_branch(&self, key: &PullRequestKey) -> AppResult<()> {
+        info!("Dry run: would update {} branch", display_key(key));
+        Ok(())
+    }
+
+    fn update_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()> {
+        warn!("Failed to update {}: {error}", display_key(key));
+        Ok(())
+    }
+
+    fn needs_attention(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()> {
+        warn!("{} needs attention: {status}", display_key(key));
+        Ok(())
+    }
+
+    fn reruns_exhausted(&self, key: &PullRequestKey, jobs: &str) -> AppResult<()> {
+        warn!(
+            "{} has no re-runs left, still failing: {jobs}",
+            display_key(key)
+        );
+        Ok(())
+    }
+}
+
+pub fn run() {
+    diagnostics::install_panic_hook();
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args = match parse_args(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{message}\n\n{USAGE}");
+            std::process::exit(2);
+        }
+    };
+
+    let result = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .map_err(AppError::from)
+        .and_then(|runtime| runtime.block_on(execute(args)));
+
+    if let Err(err) = result {
+        diagnostics::record_fatal_error("error while running pr-monitor-cli", &err);
+        eprintln!("error: {err}");
+        std::process::exit(1);
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut args = args.into_iter();
+    let mut db_path = None;
+
+    let command = loop {
+        match args.next().as_deref() {
+            Some("--db") => {
+                let path = args.next().ok_or("--db needs a path")?;
+                db_path = Some(PathBuf::from(path));
+            }
+            Some("add") => {
+                let url = args.next().ok_or("add needs a pull request url")?;
+                break Command::Add { url };
+            }
+            Some("list") => break Command::List,
+            Some("remove") => {
+                let url = args.next().ok_or("remove needs a pull request url")?;
+                break Command::Remove { url };
+            }
+            Some("mode") => {
+                let url = args.next().ok_or("mode needs a pull request url")?;
+                let value = args.next().ok_or("mode needs a mode")?;
+                let mode = MonitorMode::try_from(value.as_str()).map_err(|err| err.to_string())?;
+                break Command::Mode { url, mode };
+            }
+            Some("update-method") => {
+                let url = args
+                    .next()
+                    .ok_or("update-method needs a pull request url")?;
+                let value = args.next().ok_or("update-method needs a method")?;
+                let method = match value.as_str() {
+                    "default" => None,
+                    value => Some(UpdateMethod::try_from(value).map_err(|err| err.to_string())?),
+                };
+                let repository = match args.next().as_deref() {
+                    Some("--repo") => true,
+                    Some(other) => return Err(format!("unexpected argument: {other}")),
+                    None => false,
+                };
+                break Command::UpdateMethod {
+                    url,
+                    method,
+                    repository,
+                };
+            }
+            Some("auto-merge") => {
+                let url = args.next().ok_or("auto-merge needs a pull request url")?;
+                let value = args.next().ok_or("auto-merge needs a merge method")?;
+                let method = match value.as_str() {
+                    "off" => None,
+                    value => Some(MergeMethod::try_from(value).map_err(|err| err.to_string())?),
+                };
+                break Command::AutoMerge { url, method };
+            }
+            Some("behind-threshold") => {
+                let value = args
+                    .next()
+                    .ok_or("behind-threshold needs a number of commits")?;
+                let commits = value
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid number of commits: {value}"))?;
+                let repository_of = match args.next().as_deref() {
+                    Some("--repo") => Some(args.next().ok_or("--repo needs a pull request url")?),
+                    Some(other) => return Err(format!("unexpected argument: {other}")),
+                    None => None,
+                };
+                break Command::BehindThreshold {
+                    commits,
+                    repository_of,
+                };
+            }
+            Some("ci-policy") => {
+                let value = args.next().ok_or("ci-policy needs a policy")?;
+                let policy = CiPolicy::try_from(value.as_str()).map_err(|err| err.to_string())?;
+                break Command::CiPolicy { policy };
+            }
+            Some("auto-enqueue") => {
+                let enabled = match args.next().as_deref() {
+                    Some("on") => true,
+                    Some("off") => false,
+                    Some(other) => {
+                        return Err(format!("auto-enqueue needs on or off, not {other}"))
+                    }
+                    None => return Err("auto-enqueue needs on or off".to_owned()),
+                };
+                break Command::AutoEnqueue { enabled };
+            }
+            Some("update-budget") => {
+                let per_hour = args.next().ok_or("update-budget needs updates per hour")?;
+                let spacing = args.next().ok_or("update-budget needs spacing minutes")?;
+                let per_hour = per_hour
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid updates per hour: {per_hour}"))?;
+                let spacing = spacing
+                    .parse::<u64>()
+                    .map_err(|_| format!("invalid spacing: {spacing}"))?;
+                break Command::UpdateBudget {
+                    per_hour: (per_hour > 0).then_some(per_hour),
+                    spacing_minutes: (spacing > 0).then_some(spacing),
+                };
+            }
+            Some("rerun-jobs") => {
+                let value = args.next().ok_or("rerun-jobs needs re-runs per PR")?;
+                let budget = value
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid re-runs per PR: {value}"))?;
+                break Command::RerunJobs {
+                    budget: (budget > 0).then_some(budget),
+                    patterns: args.by_ref().collect(),
+                };
+            }
+            Some("run") => {
+                let interval_minutes = match args.next().as_deref() {
+                    Some("--interval") => {
+                        let value = args.next().ok_or("--interval needs a number of minutes")?;
+                        let minutes = value
+                            .parse::<u64>()
+                            .map_err(|_| format!("invalid interval: {value}"))?;
+                        Some(minutes)
+                    }
+                    Some(other) => return Err(format!("unexpected argument: {other}")),
+                    None => None,
+                };
+                break Command::Run { interval_minutes };
+            }
+            Some("check-once") => break Command::CheckOnce,
+            Some("audit") => break Command::Audit,
+            Some(other) => return Err(format!("unknown command: {other}")),
+            None => return Err("missing command".to_owned()),
+        }
+    };
+
+    if let Some(extra) = args.next() {
+        return Err(format!("unexpected argument: {extra}"));
+    }
+
+    Ok(Args { db_path, command })
+}
+
+async fn execute(args: Args) -> AppResult<()> {
+    let db_path = match args.db_path {
+        Some(db_path) => db_path,
+        None => default_db_path()?,
+    };
+    if let Some(parent) = db_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let storage = Storage::new(db_path)?;
+
+    match args.command {
+        Command::Add { url } => {
+            let key = parse_github_pr_url(&url).ok_or(AppError::InvalidPullRequestUrl)?;
+            let provider = OctocrabProvider::new(&github_token().await?)?;
+            let mut pull_request = provider.get_pr_details(&key).await?;
+            pull_request.mode = storage.get_default_mode().await?;
+            println!("Watching {}: {}", display_key(&key), pull_request.title);
+            storage.add_pull_request(pull_request).await?;
+        }
+        Command::List => {
+            for pr in storage.get_all_pull_requests().await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    display_key(&pr.key()),
+                    pr.state,
+                    pr.mode,
+                    pr.title
+                );
+            }
+        }
+        Command::Remove { url } => {
+            let key = parse_github_pr_url(&url).ok_or(AppError::InvalidPullRequestUrl)?;
+            storage.delete_pull_request(key.clone()).await?;
+            println!("Stopped watching {}", display_key(&key));
+        }
+        Command::Mode { url, mode } => {
+            let key = parse_github_pr_url(&url).ok_or(AppError::InvalidPullRequestUrl)?;
+            storage.set_pull_request_mode(key.clone(), mode).await?;
+            println!("{} is now {mode}", display_key(&key));
+        }
+        Command::UpdateMethod {
+            url,
+            method,
+            repository,
+        } => {
+            let key = parse_github_pr_url(&url).ok_or(AppError::InvalidPullRequestUrl)?;
+            let target = if repository {
+                storage
+                    .set_repository_update_method(key.owner.clone(), key.repo.clone(), method)
+                    .await?;
+                format!("{}/{}", key.owner, key.repo)
+            } else {
+                storage
+                    .set_pull_request_update_method(key.clone(), method)
+                    .await?;
+                display_key(&key)
+            };
+            match method {
+                Some(method) => println!("{target} is now updated with {method}"),
+                None => println!("{target} now uses the default update method"),
+            }
+        }
+        Command::AutoMerge { url, method } => {
+            let key = parse_github_pr_url(&url).ok_or(AppError::InvalidPullRequestUrl)?;
+            let provider = OctocrabProvider::new(&github_token().await?)?;
+            set_auto_merge(&storage, &provider, &key, method).await?;
+            match method {
+                Some(method) => println!("{} auto-merges with {method}", display_key(&key)),
+                None => println!("{} no longer auto-merges", display_key(&key)),
+            }
+        }
+        Command::BehindThreshold {
+            commits,
+            repository_of: Some(url),
+        } => {
+            let key = parse_github_pr_url(&url).ok_or(AppError::InvalidPullRequestUrl)?;
+            let commits = (commits > 0).then_some(commits);
+            storage
+                .set_repository_behind_threshold(key.owner.clone(), key.repo.clone(), commits)
+                .await?;
+            match commits {
+                Some(commits) => println!(
+                    "{}/{} is now updated once {commits} commit(s) behind",
+                    key.owner, key.repo
+                ),
+                None => println!("{}/{} now uses the global threshold", key.owner, key.repo),
+            }
+        }
+        Command::BehindThreshold {
+            commits,
+            repository_of: None,
+        } => {
+            storage.set_behind_threshold(commits).await?;
+            println!(
+                "Branches are now updated once {} commit(s) behind",
+                storage.get_behind_threshold().await?
+            );
+        }
+        Command::CiPolicy { policy } => {
+            storage.set_ci_policy(policy).await?;
+            println!("Behind branches are now updated with the {policy} CI policy");
+        }
+        Command::AutoEnqueue { enabled } => {
+            storage.set_auto_enqueue(enabled).await?;
+            if enabled {
+                println!("Approved PRs with passing CI are now added to their merge queue");
+            } else {
+                println!("PRs are no longer added to merge queues");
+            }
+        }
+        Command::UpdateBudget {
+            per_hour,
+            spacing_minutes,
+        } => {
+            storage.set_max_updates_per_hour(per_hour).await?;
+            storage.set_update_spacing_minutes(spacing_minutes).await?;
+            println!("Branch updates per repository are now limited");
+        }
+        Command::RerunJobs { budget, patterns } => {
+            storage.set_rerun_budget(budget).await?;
+            storage.set_rerun_job_patterns(patterns).await?;
+            match budget {
+                Some(budget) => {
+                    println!("Failed jobs are now re-run up to {budget} time(s) per PR")
+                }
+                None => println!("Failed jobs are no longer re-run"),
+            }
+        }
+        Command::Run { interval_minutes } => {
+            let refresh_time_secs = match interval_minutes {
+                Some(0) => return Err(AppError::InvalidRefreshTime),
+                Some(minutes) => minutes * 60,
+                None => storage.get_refresh_time().await?,
+            };
+            let provider =
+                OctocrabProvider::new(&github_token().await?)?.with_etag_cache(storage.clone());
+
+            let monitor = Monitor::default();
+            monitor
+                .start(
+                    storage,
+                    Arc::new(provider),
+                    refresh_time_secs,
+                    Arc::new(LogSink),
+                )
+                .await;
+            tokio::signal::ctrl_c().await?;
+            monitor.stop().await;
+        }
+        Command::CheckOnce => {
+            let provider =
+                OctocrabProvider::new(&github_token().await?)?.with_etag_cache(storage.clone());
+            check_pull_requests(&storage, &provider, &LogSink).await?;
+        }
+        Command::Audit => {
+            print!("{}", audit::to_csv(&storage.get_audit_log().await?));
+        }
+    }
+
+    Ok(())
+}
+
+async fn github_token() -> AppResult<String> {
+    if let Some(token) = std::env::var("GITHUB_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+    {
+        return Ok(token);
+    }
+
+    CredentialStore::default()
+        .get_github_token()
+        .await?
+        .ok_or(AppError::MissingToken)
+}
+
+/// Mirrors the directory the tray app keeps `monitor.db` in, so both share one list.
+fn default_db_path() -> AppResult<PathBuf> {
+    let data_dir = if cfg!(target_os = "macos") {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+        })
+    } else if cfg!(target_os = "windows") {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|home| PathBuf::from(home).join(".local").join("share"))
+            })
+    };
+
+    data_dir
+        .map(|dir| dir.join(diagnostics::APP_IDENTIFIER).join("monitor.db"))
+        .ok_or(AppError::DataDirNotFound)
+}
+
+fn display_key(key: &PullRequestKey) -> String {
+    format!("{}/{}#{}", key.owner, key.repo, key.pr_number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(values: &[&str]) -> Result<Args, String> {
+        parse_args(values.iter().map(|value| value.to_string()))
+    }
+
+    #[test]
+    fn parse_args_reads_command_and_database_path() {
+        let parsed = args(&[
+            "--db",
+            "/tmp/monitor.db",
+            "add",
+            "https://github.com/a/b/pull/1",
+        ])
+        .expect("args should parse");
+
+        assert_eq!(
+            parsed,
+            Args {
+                db_path: Some(PathBuf::from("/tmp/monitor.db")),
+                command: Command::Add {
+                    url: "https://github.com/a/b/pull/1".to_owned()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn parse_args_reads_run_interval() {
+        let parsed = args(&["run", "--interval", "5"]).expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::Run {
+                interval_minutes: Some(5)
+            }
+        );
+    }
+
+    #[test]
+    fn parse_args_reads_monitor_mode() {
+        let parsed = args(&["mode", "https://github.com/a/b/pull/1", "notify_only"])
+            .expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::Mode {
+                url: "https://github.com/a/b/pull/1".to_owned(),
+                mode: MonitorMode::NotifyOnly
+            }
+        );
+        assert!(args(&["mode", "https://github.com/a/b/pull/1", "loud"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_behind_threshold() {
+        let parsed = args(&[
+            "behind-threshold",
+            "5",
+            "--repo",
+            "https://github.com/a/b/pull/1",
+        ])
+        .expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::BehindThreshold {
+                commits: 5,
+                repository_of: Some("https://github.com/a/b/pull/1".to_owned()),
+            }
+        );
+        assert!(args(&["behind-threshold", "many"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_ci_policy() {
+        let parsed = args(&["ci-policy", "not_while_pending"]).expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::CiPolicy {
+                policy: CiPolicy::NotWhilePending
+            }
+        );
+        assert!(args(&["ci-policy", "sometimes"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_auto_enqueue() {
+        assert_eq!(
+            args(&["auto-enqueue", "on"])
+                .expect("args should parse")
+                .command,
+            Command::AutoEnqueue { enabled: true }
+        );
+        assert!(args(&["auto-enqueue", "yes"]).is_err());
+        assert!(args(&["auto-enqueue"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_update_budget() {
+        let parsed = args(&["update-budget", "6", "0"]).expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::UpdateBudget {
+                per_hour: Some(6),
+                spacing_minutes: None,
+            }
+        );
+        assert!(args(&["update-budget", "6"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_rerun_jobs() {
+        let parsed = args(&["rerun-jobs", "2", "test *", "e2e*"]).expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::RerunJobs {
+                budget: Some(2),
+                patterns: vec!["test *".to_owned(), "e2e*".to_owned()],
+            }
+        );
+        assert!(args(&["rerun-jobs", "many"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_update_method() {
+        let parsed = args(&[
+            "update-method",
+            "https://github.com/a/b/pull/1",
+            "rebase",
+            "--repo",
+        ])
+        .expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::UpdateMethod {
+                url: "https://github.com/a/b/pull/1".to_owned(),
+                method: Some(UpdateMethod::Rebase),
+                repository: true,
+            }
+        );
+        assert_eq!(
+            args(&["update-method", "https://github.com/a/b/pull/1", "default"])
+                .expect("args should parse")
+                .command,
+            Command::UpdateMethod {
+                url: "https://github.com/a/b/pull/1".to_owned(),
+                method: None,
+                repository: false,
+            }
+        );
+        assert!(args(&["update-method", "https://github.com/a/b/pull/1", "squash"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_reads_auto_merge() {
+        let parsed = args(&["auto-merge", "https://github.com/a/b/pull/1", "squash"])
+            .expect("args should parse");
+
+        assert_eq!(
+            parsed.command,
+            Command::AutoMerge {
+                url: "https://github.com/a/b/pull/1".to_owned(),
+                method: Some(MergeMethod::Squash),
+            }
+        );
+        assert_eq!(
+            args(&["auto-merge", "https://github.com/a/b/pull/1", "off"])
+                .expect("args should parse")
+                .command,
+            Command::AutoMerge {
+                url: "https://github.com/a/b/pull/1".to_owned(),
+                method: None,
+            }
+        );
+        assert!(args(&["auto-merge", "https://github.com/a/b/pull/1", "fast"]).is_err());
+    }
+
+    #[test]
+    fn parse_args_rejects_unknown_and_extra_arguments() {
+        assert!(args(&["watch"]).is_err());
+        assert!(args(&["list", "everything"]).is_err());
+        assert!(args(&["audit", "--all"]).is_err());
+        assert!(args(&["run", "--interval", "soon"]).is_err());
+        assert!(args(&[]).is_err());
+    }
+}
@@ -1,9 +1,15 @@
-use crate::app::AppState;
-use crate::domain::{PullRequestKey, PullRequestModel};
+use crate::app::{AppState, TauriSink};
+use crate::audit;
+use crate::domain::{
+    CiPolicy, MergeMethod, MonitorMode, PullRequestEvent, PullRequestKey, PullRequestModel,
+    RepositoryBehindThreshold, RepositoryUpdateMethod, UpdateMethod,
+};
 use crate::error::{AppError, AppResult};
-use crate::github::{get_pr_details, parse_github_pr_url};
+use crate::github::{parse_github_pr_url, PullRequestProvider};
+use crate::monitor::{self, RateLimitStatus};
 use log::{info, warn};
-use tauri::{Emitter, State, Wry};
+use std::sync::Arc;
+use tauri::{Emitter, Manager, State, Wry};
 
 fn into_command_error(err: impl std::fmt::Display) -> String {
     err.to_string()
@@ -63,10 +69,19 @@ pub async fn has_token(state: State<'_, AppState>) -> Result<bool, String> {
 }
 
 #[tauri::command]
-pub async fn add_token(state: State<'_, AppState>, token: String) -> Result<(), String> {
+pub async fn add_token(
+    app_handle: tauri::AppHandle<Wry>,
+    state: State<'_, AppState>,
+    token: String,
+) -> Result<(), String> {
     state
         .credentials
         .set_github_token(token)
+        .await
+        .map_err(into_command_error)?;
+    state.github.invalidate();
+    state.monitor.stop().await;
+    start_monitor(app_handle, &state)
         .await
         .map_err(into_command_error)
 }
@@ -99,7 +114,8 @@ async fn add_item_inner(state: &AppState, url: &str) -> AppResult<Vec<PullReques
         .get_github_token()
         .await?
         .ok_or(AppError::MissingToken)?;
-    let pull_request = get_pr_details(&token, &key).await?;
+    let mut pull_request = state.github.provider(&token)?.get_pr_details(&key).await?;
+    pull_request.mode = state.storage.get_default_mode().await?;
 
     state.storage.add_pull_request(pull_request).await?;
     Ok(state.storage.get_all_pull_requests().await?)
@@ -150,6 +166,259 @@ pub async fn delete_pr(
         .map_err(into_command_error)
 }
 
+#[tauri::command]
+pub async fn set_pr_mode(
+    state: State<'_, AppState>,
+    owner: String,
+    repo: String,
+    pr_number: u64,
+    mode: MonitorMode,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_pull_request_mode(PullRequestKey::new(owner, repo, pr_number), mode)
+        .await
+        .map_err(into_command_error)
+}
+
+/// `None` makes the PR follow its repository's update method.
+#[tauri::command]
+pub async fn set_pr_update_method(
+    state: State<'_, AppState>,
+    owner: String,
+    repo: String,
+    pr_number: u64,
+    method: Option<UpdateMethod>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_pull_request_update_method(PullRequestKey::new(owner, repo, pr_number), method)
+        .await
+        .map_err(into_command_error)
+}
+
+/// Keeps GitHub auto-merge on for the PR with `method`, or turns it off with `None`.
+#[tauri::command]
+pub async fn set_pr_auto_merge(
+    state: State<'_, AppState>,
+    owner: String,
+    repo: String,
+    pr_number: u64,
+    method: Option<MergeMethod>,
+) -> Result<(), String> {
+    set_pr_auto_merge_inner(&state, PullRequestKey::new(owner, repo, pr_number), method)
+        .await
+        .map_err(into_command_error)
+}
+
+async fn set_pr_auto_merge_inner(
+    state: &AppState,
+    key: PullRequestKey,
+    method: Option<MergeMethod>,
+) -> AppResult<()> {
+    let token = state
+        .credentials
+        .get_github_token()
+        .await?
+        .ok_or(AppError::MissingToken)?;
+    let provider = state.github.provider(&token)?;
+    monitor::set_auto_merge(&state.storage, provider.as_ref(), &key, method).await
+}
+
+#[tauri::command]
+pub async fn get_repo_update_methods(
+    state: State<'_, AppState>,
+) -> Result<Vec<RepositoryUpdateMethod>, String> {
+    let methods = state
+        .storage
+        .get_repository_update_methods()
+        .await
+        .map_err(into_command_error)?;
+
+    let mut methods: Vec<_> = methods
+        .into_iter()
+        .map(|((owner, repo), method)| RepositoryUpdateMethod {
+            owner,
+            repo,
+            method,
+        })
+        .collect();
+    methods.sort_by(|a, b| (&a.owner, &a.repo).cmp(&(&b.owner, &b.repo)));
+    Ok(methods)
+}
+
+/// `None` goes back to the default update method for the repository.
+#[tauri::command]
+pub async fn set_repo_update_method(
+    state: State<'_, AppState>,
+    owner: String,
+    repo: String,
+    method: Option<UpdateMethod>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_repository_update_method(owner, repo, method)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_behind_threshold(state: State<'_, AppState>) -> Result<u32, String> {
+    state
+        .storage
+        .get_behind_threshold()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_behind_threshold(state: State<'_, AppState>, commits: u32) -> Result<(), String> {
+    state
+        .storage
+        .set_behind_threshold(commits)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_repo_behind_thresholds(
+    state: State<'_, AppState>,
+) -> Result<Vec<RepositoryBehindThreshold>, String> {
+    let thresholds = state
+        .storage
+        .get_repository_behind_thresholds()
+        .await
+        .map_err(into_command_error)?;
+
+    let mut thresholds: Vec<_> = thresholds
+        .into_iter()
+        .map(|((owner, repo), commits)| RepositoryBehindThreshold {
+            owner,
+            repo,
+            commits,
+        })
+        .collect();
+    thresholds.sort_by(|a, b| (&a.owner, &a.repo).cmp(&(&b.owner, &b.repo)));
+    Ok(thresholds)
+}
+
+/// `None` makes the repository follow the global threshold again.
+#[tauri::command]
+pub async fn set_repo_behind_threshold(
+    state: State<'_, AppState>,
+    owner: String,
+    repo: String,
+    commits: Option<u32>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_repository_behind_threshold(owner, repo, commits)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_ci_policy(state: State<'_, AppState>) -> Result<CiPolicy, String> {
+    state
+        .storage
+        .get_ci_policy()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_ci_policy(state: State<'_, AppState>, policy: CiPolicy) -> Result<(), String> {
+    state
+        .storage
+        .set_ci_policy(policy)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_default_mode(state: State<'_, AppState>) -> Result<MonitorMode, String> {
+    state
+        .storage
+        .get_default_mode()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_default_mode(state: State<'_, AppState>, mode: MonitorMode) -> Result<(), String> {
+    state
+        .storage
+        .set_default_mode(mode)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_pr_history(
+    state: State<'_, AppState>,
+    owner: String,
+    repo: String,
+    pr_number: u64,
+) -> Result<Vec<PullRequestEvent>, String> {
+    state
+        .storage
+        .get_pull_request_events(PullRequestKey::new(owner, repo, pr_number))
+        .await
+        .map_err(into_command_error)
+}
+
+/// Writes the audit log as CSV to the downloads folder and returns the file's path.
+#[tauri::command]
+pub async fn export_audit_log(
+    app_handle: tauri::AppHandle<Wry>,
+    state: State<'_, AppState>,
+) -> Result<String, String> {
+    let entries = state
+        .storage
+        .get_audit_log()
+        .await
+        .map_err(into_command_error)?;
+    let dir = app_handle
+        .path()
+        .download_dir()
+        .map_err(into_command_error)?;
+    let path = dir.join(format!(
+        "pr-monitor-audit-{}.csv",
+        chrono::Local::now().format("%Y%m%d-%H%M%S")
+    ));
+    std::fs::write(&path, audit::to_csv(&entries)).map_err(into_command_error)?;
+    info!(
+        "Exported {} audit entries to {}",
+        entries.len(),
+        path.display()
+    );
+
+    Ok(path.display().to_string())
+}
+
+#[tauri::command]
+pub fn get_rate_limit(state: State<'_, AppState>) -> Option<RateLimitStatus> {
+    state.monitor.rate_limit()
+}
+
+#[tauri::command]
+pub async fn get_auto_enqueue(state: State<'_, AppState>) -> Result<bool, String> {
+    state
+        .storage
+        .get_auto_enqueue()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_auto_enqueue(state: State<'_, AppState>, enabled: bool) -> Result<(), String> {
+    state
+        .storage
+        .set_auto_enqueue(enabled)
+        .await
+        .map_err(into_command_error)
+}
+
 #[tauri::command]
 pub async fn get_show_notification(state: State<'_, AppState>) -> Result<bool, String> {
     state
@@ -168,6 +437,136 @@ pub async fn set_show_notification(state: State<'_, AppState>, show: bool) -> Re
         .map_err(into_command_error)
 }
 
+#[tauri::command]
+pub async fn get_reminder_hours(state: State<'_, AppState>) -> Result<Option<u64>, String> {
+    state
+        .storage
+        .get_reminder_hours()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_reminder_hours(
+    state: State<'_, AppState>,
+    hours: Option<u64>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_reminder_hours(hours)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_max_updates_per_hour(state: State<'_, AppState>) -> Result<Option<u32>, String> {
+    state
+        .storage
+        .get_max_updates_per_hour()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_max_updates_per_hour(
+    state: State<'_, AppState>,
+    limit: Option<u32>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_max_updates_per_hour(limit)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_rerun_budget(state: State<'_, AppState>) -> Result<Option<u32>, String> {
+    state
+        .storage
+        .get_rerun_budget()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_rerun_budget(
+    state: State<'_, AppState>,
+    budget: Option<u32>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_rerun_budget(budget)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_rerun_job_patterns(state: State<'_, AppState>) -> Result<Vec<String>, String> {
+    state
+        .storage
+        .get_rerun_job_patterns()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_rerun_job_patterns(
+    state: State<'_, AppState>,
+    patterns: Vec<String>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_rerun_job_patterns(patterns)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_update_spacing_minutes(state: State<'_, AppState>) -> Result<Option<u64>, String> {
+    state
+        .storage
+        .get_update_spacing_minutes()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_update_spacing_minutes(
+    state: State<'_, AppState>,
+    minutes: Option<u64>,
+) -> Result<(), String> {
+    state
+        .storage
+        .set_update_spacing_minutes(minutes)
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn get_max_concurrent_checks(state: State<'_, AppState>) -> Result<usize, String> {
+    state
+        .storage
+        .get_max_concurrent_checks()
+        .await
+        .map_err(into_command_error)
+}
+
+#[tauri::command]
+pub async fn set_max_concurrent_checks(
+    state: State<'_, AppState>,
+    limit: usize,
+) -> Result<(), String> {
+    if limit == 0 {
+        return Err(into_command_error(AppError::InvalidMaxConcurrentChecks));
+    }
+
+    state
+        .storage
+        .set_max_concurrent_checks(limit)
+        .await
+        .map_err(into_command_error)
+}
+
 #[tauri::command]
 pub async fn get_theme(state: State<'_, AppState>) -> Result<String, String> {
     state.storage.get_theme().await.map_err(into_command_error)
@@ -191,7 +590,12 @@ async fn start_monitor(app_handle: tauri::AppHandle<Wry>, state: &AppState) -> A
 
     state
         .monitor
-        .start(state.storage.clone(), token, refresh_time_secs, app_handle)
+        .start(
+            state.storage.clone(),
+            state.github.provider(&token)?,
+            refresh_time_secs,
+            Arc::new(TauriSink::new(app_handle)),
+        )
         .await;
 
     Ok(())
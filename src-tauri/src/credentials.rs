@@ -14,6 +14,7 @@ pub struct CredentialStore {
 }
 
 impl CredentialStore {
+    #[cfg(feature = "desktop")]
     pub async fn set_github_token(&self, token: String) -> CredentialResult<()> {
         let _guard = self.access.lock().await;
         tokio::task::spawn_blocking(move || -> CredentialResult<()> {
@@ -5,7 +5,7 @@ use std::path::PathBuf;
 use std::sync::Once;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-const APP_IDENTIFIER: &str = "pr-monitor.guibeira.dev";
+pub(crate) const APP_IDENTIFIER: &str = "pr-monitor.guibeira.dev";
 
 static PANIC_HOOK: Once = Once::new();
 
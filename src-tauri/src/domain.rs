@@ -1,7 +1,8 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::fmt;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct PullRequestKey {
     pub owner: String,
     pub repo: String,
@@ -22,7 +23,9 @@ impl PullRequestKey {
 #[serde(rename_all = "lowercase")]
 pub enum PullRequestState {
     Open,
+    /// Closed without being merged. It can still be reopened.
     Closed,
+    Merged,
 }
 
 impl PullRequestState {
@@ -30,6 +33,7 @@ impl PullRequestState {
         match self {
             Self::Open => "open",
             Self::Closed => "closed",
+            Self::Merged => "merged",
         }
     }
 }
@@ -47,6 +51,7 @@ impl TryFrom<&str> for PullRequestState {
         match value {
             "open" => Ok(Self::Open),
             "closed" => Ok(Self::Closed),
+            "merged" => Ok(Self::Merged),
             other => Err(PullRequestStateParseError(other.to_owned())),
         }
     }
@@ -56,6 +61,246 @@ impl TryFrom<&str> for PullRequestState {
 #[error("invalid pull request state: {0}")]
 pub struct PullRequestStateParseError(String);
 
+/// How much the monitor may do on its own for one PR.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorMode {
+    /// Update the branch when it falls behind.
+    #[default]
+    AutoUpdate,
+    /// Never push to the branch; notify when it falls behind instead.
+    NotifyOnly,
+    /// Track the status without notifying or pushing anything.
+    Silent,
+}
+
+impl MonitorMode {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::AutoUpdate => "auto_update",
+            Self::NotifyOnly => "notify_only",
+            Self::Silent => "silent",
+        }
+    }
+}
+
+impl fmt::Display for MonitorMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for MonitorMode {
+    type Error = MonitorModeParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "auto_update" => Ok(Self::AutoUpdate),
+            "notify_only" => Ok(Self::NotifyOnly),
+            "silent" => Ok(Self::Silent),
+            other => Err(MonitorModeParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid monitor mode: {0}")]
+pub struct MonitorModeParseError(String);
+
+/// How a behind branch is brought up to date with its base.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateMethod {
+    /// Merge the base into the branch, adding a merge commit.
+    #[default]
+    Merge,
+    /// Rebase the branch onto the base, keeping its history linear.
+    Rebase,
+}
+
+impl UpdateMethod {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Merge => "merge",
+            Self::Rebase => "rebase",
+        }
+    }
+}
+
+impl fmt::Display for UpdateMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for UpdateMethod {
+    type Error = UpdateMethodParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "merge" => Ok(Self::Merge),
+            "rebase" => Ok(Self::Rebase),
+            other => Err(UpdateMethodParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid update method: {0}")]
+pub struct UpdateMethodParseError(String);
+
+/// How GitHub merges a PR into its base.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeMethod {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+impl MergeMethod {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Merge => "merge",
+            Self::Squash => "squash",
+            Self::Rebase => "rebase",
+        }
+    }
+
+    /// The matching `PullRequestMergeMethod` value in GitHub's GraphQL API.
+    pub const fn as_graphql(self) -> &'static str {
+        match self {
+            Self::Merge => "MERGE",
+            Self::Squash => "SQUASH",
+            Self::Rebase => "REBASE",
+        }
+    }
+}
+
+impl fmt::Display for MergeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for MergeMethod {
+    type Error = MergeMethodParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "merge" => Ok(Self::Merge),
+            "squash" => Ok(Self::Squash),
+            "rebase" => Ok(Self::Rebase),
+            other => Err(MergeMethodParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid merge method: {0}")]
+pub struct MergeMethodParseError(String);
+
+/// What CI says about a PR's head commit, combining commit statuses and check runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiState {
+    /// Everything reported has passed.
+    Passing,
+    /// Something is still queued or running, and nothing has failed.
+    Pending,
+    /// At least one status or check run failed.
+    Failing,
+    /// Nothing reports on this commit.
+    Missing,
+}
+
+impl CiState {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Passing => "passing",
+            Self::Pending => "pending",
+            Self::Failing => "failing",
+            Self::Missing => "missing",
+        }
+    }
+}
+
+impl fmt::Display for CiState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// When a behind branch may be updated, given the CI state of its head commit.
+/// Updating restarts CI, so holding back saves CI minutes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CiPolicy {
+    /// Update regardless of CI.
+    #[default]
+    Always,
+    /// Wait for running checks to finish first.
+    NotWhilePending,
+    /// Only update once CI passed. Commits without any CI count as passed.
+    OnlyWhenPassing,
+}
+
+impl CiPolicy {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::NotWhilePending => "not_while_pending",
+            Self::OnlyWhenPassing => "only_when_passing",
+        }
+    }
+
+    pub fn allows(self, state: CiState) -> bool {
+        match self {
+            Self::Always => true,
+            Self::NotWhilePending => state != CiState::Pending,
+            Self::OnlyWhenPassing => matches!(state, CiState::Passing | CiState::Missing),
+        }
+    }
+}
+
+impl fmt::Display for CiPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for CiPolicy {
+    type Error = CiPolicyParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "always" => Ok(Self::Always),
+            "not_while_pending" => Ok(Self::NotWhilePending),
+            "only_when_passing" => Ok(Self::OnlyWhenPassing),
+            other => Err(CiPolicyParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid CI policy: {0}")]
+pub struct CiPolicyParseError(String);
+
+/// The update method chosen for every PR of a repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryUpdateMethod {
+    pub owner: String,
+    pub repo: String,
+    pub method: UpdateMethod,
+}
+
+/// How many commits behind a repository's PRs get before they are updated.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RepositoryBehindThreshold {
+    pub owner: String,
+    pub repo: String,
+    pub commits: u32,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct PullRequestModel {
     pub owner: String,
@@ -63,8 +308,27 @@ pub struct PullRequestModel {
     pub pr_number: u64,
     pub title: String,
     pub state: PullRequestState,
-    pub closed_at: Option<String>,
+    pub closed_at: Option<DateTime<Utc>>,
     pub url: String,
+    pub last_error: Option<String>,
+    /// What the last successful check found, kept so the UI has it before the next tick.
+    pub last_status: Option<PrStatus>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    pub added_at: Option<DateTime<Utc>>,
+    pub merged_at: Option<DateTime<Utc>>,
+    /// When `last_status` last changed to its current value.
+    pub status_changed_at: Option<DateTime<Utc>>,
+    /// When the current status last raised a needs-attention notification.
+    pub last_notified_at: Option<DateTime<Utc>>,
+    pub mode: MonitorMode,
+    /// Overrides the repository's update method for this PR.
+    pub update_method: Option<UpdateMethod>,
+    /// How many commits the branch was behind its base at the last check, if known.
+    pub behind_by: Option<u32>,
+    /// Auto-merge is kept on with this method. `None` leaves auto-merge alone.
+    pub auto_merge: Option<MergeMethod>,
+    /// Whether GitHub had auto-merge on at the last check.
+    pub auto_merge_enabled: bool,
 }
 
 impl PullRequestModel {
@@ -73,12 +337,268 @@ impl PullRequestModel {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PrStatus {
     Merged,
+    /// Closed without being merged.
+    Closed,
     Behind,
     UpToDate,
     Conflicts,
     Blocked,
+    /// Waiting in the base branch's merge queue.
+    Queued,
+    /// GitHub has not finished computing the merge state yet.
+    Pending,
     Unknown,
 }
+
+impl PrStatus {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Merged => "merged",
+            Self::Closed => "closed",
+            Self::Behind => "behind",
+            Self::UpToDate => "up_to_date",
+            Self::Conflicts => "conflicts",
+            Self::Blocked => "blocked",
+            Self::Queued => "queued",
+            Self::Pending => "pending",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for PrStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<&str> for PrStatus {
+    type Error = PrStatusParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "merged" => Ok(Self::Merged),
+            "closed" => Ok(Self::Closed),
+            "behind" => Ok(Self::Behind),
+            "up_to_date" => Ok(Self::UpToDate),
+            "conflicts" => Ok(Self::Conflicts),
+            "blocked" => Ok(Self::Blocked),
+            "queued" => Ok(Self::Queued),
+            "pending" => Ok(Self::Pending),
+            "unknown" => Ok(Self::Unknown),
+            other => Err(PrStatusParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid pull request status: {0}")]
+pub struct PrStatusParseError(String);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequestSnapshot {
+    pub title: String,
+    pub state: PullRequestState,
+    pub status: PrStatus,
+    pub head_sha: String,
+    /// The branch the PR merges into.
+    pub base_ref: String,
+    /// Whether auto-merge is on, when the answer says.
+    pub auto_merge_enabled: Option<bool>,
+    /// Whether the base branch merges through a merge queue, when the answer says.
+    pub merge_queue: Option<bool>,
+    /// Whether reviews let the PR merge: approved, or no review required.
+    pub approved: Option<bool>,
+}
+
+/// The last REST answer for a PR, replayed when GitHub says its ETag still matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedPullRequest {
+    pub etag: String,
+    pub snapshot: PullRequestSnapshot,
+}
+
+/// What kind of entry a PR's history timeline holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PullRequestEventKind {
+    StatusChanged,
+    BranchUpdated,
+    BranchUpdateFailed,
+    Merged,
+    Closed,
+    Reopened,
+    JobsRerun,
+    /// CI still failed after the PR used up its re-runs.
+    RerunsExhausted,
+}
+
+impl PullRequestEventKind {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::StatusChanged => "status_changed",
+            Self::BranchUpdated => "branch_updated",
+            Self::BranchUpdateFailed => "branch_update_failed",
+            Self::Merged => "merged",
+            Self::Closed => "closed",
+            Self::Reopened => "reopened",
+            Self::JobsRerun => "jobs_rerun",
+            Self::RerunsExhausted => "reruns_exhausted",
+        }
+    }
+}
+
+impl TryFrom<&str> for PullRequestEventKind {
+    type Error = PullRequestEventKindParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "status_changed" => Ok(Self::StatusChanged),
+            "branch_updated" => Ok(Self::BranchUpdated),
+            "branch_update_failed" => Ok(Self::BranchUpdateFailed),
+            "merged" => Ok(Self::Merged),
+            "closed" => Ok(Self::Closed),
+            "reopened" => Ok(Self::Reopened),
+            "jobs_rerun" => Ok(Self::JobsRerun),
+            "reruns_exhausted" => Ok(Self::RerunsExhausted),
+            other => Err(PullRequestEventKindParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid pull request event kind: {0}")]
+pub struct PullRequestEventKindParseError(String);
+
+/// One entry in a PR's history.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PullRequestEvent {
+    pub kind: PullRequestEventKind,
+    /// The status moved to, for `StatusChanged`.
+    pub status: Option<PrStatus>,
+    /// The error message, for `BranchUpdateFailed`, or the job names, for
+    /// `JobsRerun` and `RerunsExhausted`.
+    pub detail: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+impl PullRequestEvent {
+    pub fn new(kind: PullRequestEventKind, at: DateTime<Utc>) -> Self {
+        Self {
+            kind,
+            status: None,
+            detail: None,
+            at,
+        }
+    }
+}
+
+/// A change the monitor pushed to GitHub on the user's behalf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Merged the base into the branch.
+    UpdateBranch,
+    /// Rebased the branch onto the base.
+    RebaseBranch,
+    EnableAutoMerge,
+    DisableAutoMerge,
+    /// Added the PR to its base branch's merge queue.
+    Enqueue,
+    /// Started the failed jobs of a GitHub Actions run again.
+    RerunFailedJobs,
+}
+
+impl AuditAction {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::UpdateBranch => "update_branch",
+            Self::RebaseBranch => "rebase_branch",
+            Self::EnableAutoMerge => "enable_auto_merge",
+            Self::DisableAutoMerge => "disable_auto_merge",
+            Self::Enqueue => "enqueue",
+            Self::RerunFailedJobs => "rerun_failed_jobs",
+        }
+    }
+}
+
+impl TryFrom<&str> for AuditAction {
+    type Error = AuditParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "update_branch" => Ok(Self::UpdateBranch),
+            "rebase_branch" => Ok(Self::RebaseBranch),
+            "enable_auto_merge" => Ok(Self::EnableAutoMerge),
+            "disable_auto_merge" => Ok(Self::DisableAutoMerge),
+            "enqueue" => Ok(Self::Enqueue),
+            "rerun_failed_jobs" => Ok(Self::RerunFailedJobs),
+            other => Err(AuditParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditResult {
+    Succeeded,
+    Failed,
+}
+
+impl AuditResult {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Succeeded => "succeeded",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+impl TryFrom<&str> for AuditResult {
+    type Error = AuditParseError;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value {
+            "succeeded" => Ok(Self::Succeeded),
+            "failed" => Ok(Self::Failed),
+            other => Err(AuditParseError(other.to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid audit log value: {0}")]
+pub struct AuditParseError(String);
+
+/// One write the monitor made against GitHub.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    pub key: PullRequestKey,
+    pub action: AuditAction,
+    pub head_sha_before: String,
+    /// Filled in by the first check that sees the branch move, since GitHub
+    /// applies the write after answering.
+    pub head_sha_after: Option<String>,
+    pub result: AuditResult,
+    pub error: Option<String>,
+    pub at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PullRequestErrorEvent {
+    #[serde(flatten)]
+    pub key: PullRequestKey,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PullRequestStatusEvent {
+    #[serde(flatten)]
+    pub key: PullRequestKey,
+    pub status: PrStatus,
+}
@@ -1,4 +1,9 @@
-use crate::domain::PullRequestStateParseError;
+use crate::domain::{
+    AuditParseError, MergeMethodParseError, MonitorModeParseError, PrStatusParseError,
+    PullRequestEventKindParseError, PullRequestStateParseError, UpdateMethodParseError,
+};
+use chrono::{DateTime, Utc};
+use http::StatusCode;
 
 pub type AppResult<T> = Result<T, AppError>;
 
@@ -19,9 +24,15 @@ pub enum AppError {
     #[error("Refresh time must be at least 1 minute")]
     InvalidRefreshTime,
 
+    #[error("At least one check must be allowed at a time")]
+    InvalidMaxConcurrentChecks,
+
     #[error("Failed to parse PR")]
     InvalidPullRequestUrl,
 
+    #[error("Can't find a data directory for the database, pass --db")]
+    DataDirNotFound,
+
     #[error("window `{0}` was not found")]
     WindowNotFound(&'static str),
 
@@ -30,6 +41,9 @@ pub enum AppError {
 
     #[error("failed to show notification: {0}")]
     Notification(String),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -40,11 +54,26 @@ pub enum GithubError {
     #[error("GitHub request failed: {0}")]
     Request(#[source] Box<octocrab::Error>),
 
+    #[error("GitHub query failed: {0}")]
+    Graphql(String),
+
+    #[error("GitHub answered with an unexpected {0}")]
+    UnexpectedStatus(StatusCode),
+
+    #[error("Unexpected response from GitHub: {0}")]
+    Decode(#[from] serde_json::Error),
+
+    #[error("GitHub rate limit reached, waiting until {reset_at}")]
+    RateLimited { reset_at: DateTime<Utc> },
+
     #[error("Can't load pr details")]
     PullRequestDetailsUnavailable,
 
     #[error("Can't update pr branch")]
     UpdateBranchUnavailable,
+
+    #[error("The branch moved since it was checked, leaving it for the next check")]
+    HeadMoved,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -61,8 +90,32 @@ pub enum StorageError {
     #[error("Pull request already exists")]
     PullRequestAlreadyExists,
 
+    #[error("Pull request is not being watched")]
+    PullRequestNotFound,
+
+    #[error("database schema version {found} is newer than this app supports ({supported})")]
+    UnsupportedSchemaVersion { found: u32, supported: u32 },
+
     #[error(transparent)]
     InvalidPullRequestState(#[from] PullRequestStateParseError),
+
+    #[error(transparent)]
+    InvalidPullRequestEvent(#[from] PullRequestEventKindParseError),
+
+    #[error(transparent)]
+    InvalidAuditEntry(#[from] AuditParseError),
+
+    #[error(transparent)]
+    InvalidMonitorMode(#[from] MonitorModeParseError),
+
+    #[error(transparent)]
+    InvalidUpdateMethod(#[from] UpdateMethodParseError),
+
+    #[error(transparent)]
+    InvalidMergeMethod(#[from] MergeMethodParseError),
+
+    #[error(transparent)]
+    InvalidPrStatus(#[from] PrStatusParseError),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -1,18 +1,133 @@
-use crate::domain::{PrStatus, PullRequestKey, PullRequestModel, PullRequestState};
+use crate::domain::{
+    CachedPullRequest, CiState, MergeMethod, MonitorMode, PrStatus, PullRequestKey,
+    PullRequestModel, PullRequestSnapshot, PullRequestState, UpdateMethod,
+};
 use crate::error::GithubError;
-use log::error;
-use octocrab::models::pulls::MergeableState;
+use crate::storage::Storage;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeDelta, Utc};
+use futures::stream::{self, StreamExt};
+use http::{HeaderMap, Method, StatusCode};
+use log::{debug, error, warn};
+use octocrab::etag::{EntityTag, Etagged};
+use octocrab::models::pulls::{MergeableState, PullRequest};
 use octocrab::Octocrab;
 use regex::Regex;
-use std::sync::LazyLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex, PoisonError};
+use std::time::Duration;
 
 static GITHUB_PR_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
     Regex::new(r"^https?://(?:www\.)?github\.com/([^/?#]+)/([^/?#]+)/pull/(\d+)/?(?:[?#].*)?$")
         .expect("static GitHub PR URL regex should be valid")
 });
 
+/// How many PRs are folded into one GraphQL query. Bigger queries cost more
+/// rate limit points and get slow to resolve on GitHub's side.
+const SNAPSHOT_BATCH_SIZE: usize = 50;
+
+/// Pauses between re-polls while GitHub is still computing a merge state.
+const MERGE_STATE_RETRY_DELAYS: [Duration; 3] = [
+    Duration::from_secs(1),
+    Duration::from_secs(2),
+    Duration::from_secs(4),
+];
+
+/// How long to wait when GitHub throttles us without saying for how long.
+const DEFAULT_RETRY_AFTER_SECS: i64 = 60;
+
 type GithubResult<T> = Result<T, GithubError>;
 
+/// The request budget GitHub reported for one API resource (`core`, `graphql`, ...).
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RateLimit {
+    pub resource: String,
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset_at: DateTime<Utc>,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        Some(Self {
+            resource: header_str(headers, "x-ratelimit-resource")?.to_owned(),
+            limit: header_number(headers, "x-ratelimit-limit")?,
+            remaining: header_number(headers, "x-ratelimit-remaining")?,
+            reset_at: DateTime::from_timestamp(
+                header_number(headers, "x-ratelimit-reset")?
+                    .try_into()
+                    .ok()?,
+                0,
+            )?,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GraphqlPullRequest {
+    title: String,
+    state: String,
+    merged: bool,
+    merge_state_status: String,
+    head_ref_oid: String,
+    base_ref_name: String,
+    auto_merge_request: Option<Value>,
+    is_in_merge_queue: bool,
+    is_merge_queue_enabled: bool,
+    review_decision: Option<String>,
+}
+
+/// `GET /repos/{owner}/{repo}/commits/{ref}/status`
+#[derive(Debug, Deserialize)]
+struct CombinedStatus {
+    state: String,
+    total_count: u64,
+}
+
+/// A failed GitHub Actions job on a PR's head commit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FailedJob {
+    pub name: String,
+    /// The workflow run the job belongs to, which is what gets re-run.
+    pub run_id: u64,
+}
+
+/// `GET /repos/{owner}/{repo}/commits/{ref}/check-runs`
+#[derive(Debug, Deserialize)]
+struct CheckRuns {
+    check_runs: Vec<CheckRun>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRun {
+    name: String,
+    status: String,
+    conclusion: Option<String>,
+    details_url: Option<String>,
+    app: Option<CheckRunApp>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CheckRunApp {
+    slug: String,
+}
+
+static ACTIONS_RUN_URL_REGEX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"/actions/runs/(\d+)/job/")
+        .expect("static GitHub Actions job URL regex should be valid")
+});
+
+/// `GET /repos/{owner}/{repo}/compare/{base}...{head}`
+#[derive(Debug, Deserialize)]
+struct Comparison {
+    behind_by: u32,
+}
+
 pub fn parse_github_pr_url(url: &str) -> Option<PullRequestKey> {
     let caps = GITHUB_PR_URL_REGEX.captures(url)?;
 
@@ -23,42 +138,437 @@ pub fn parse_github_pr_url(url: &str) -> Option<PullRequestKey> {
     Some(PullRequestKey::new(owner, repo, pr_number))
 }
 
-pub async fn update_pr_branch(key: &PullRequestKey, token: &str) -> GithubResult<()> {
-    let octocrab = Octocrab::builder()
-        .personal_token(token.to_owned())
-        .build()
-        .map_err(|err| GithubError::Client(Box::new(err)))?;
+#[async_trait]
+pub trait PullRequestProvider: Send + Sync {
+    async fn get_pr_details(&self, key: &PullRequestKey) -> GithubResult<PullRequestModel>;
 
-    octocrab
-        .pulls(&key.owner, &key.repo)
-        .update_branch(key.pr_number)
-        .await
-        .map_err(|err| {
-            error!("Error: {err:?}");
-            GithubError::UpdateBranchUnavailable
-        })?;
+    /// Loads every PR in as few requests as possible, with at most
+    /// `max_concurrent` requests in flight. Results follow the order of `keys`.
+    async fn fetch_snapshots(
+        &self,
+        keys: &[PullRequestKey],
+        max_concurrent: usize,
+    ) -> Vec<GithubResult<PullRequestSnapshot>>;
+
+    /// Brings the branch up to date with its base using `method`. GitHub rejects
+    /// the update with [`GithubError::HeadMoved`] when the head is no longer
+    /// `expected_head_sha`.
+    async fn update_pr_branch(
+        &self,
+        key: &PullRequestKey,
+        method: UpdateMethod,
+        expected_head_sha: &str,
+    ) -> GithubResult<()>;
+
+    /// How many commits `base_ref` has that `head_sha` does not.
+    async fn behind_by(
+        &self,
+        key: &PullRequestKey,
+        base_ref: &str,
+        head_sha: &str,
+    ) -> GithubResult<u32>;
+
+    /// Combines the commit statuses and check runs reported on `head_sha`.
+    async fn ci_state(&self, key: &PullRequestKey, head_sha: &str) -> GithubResult<CiState>;
+
+    /// Turns on GitHub's auto-merge, so the PR merges with `method` once its
+    /// requirements pass.
+    async fn enable_auto_merge(
+        &self,
+        key: &PullRequestKey,
+        method: MergeMethod,
+        expected_head_sha: &str,
+    ) -> GithubResult<()>;
+
+    async fn disable_auto_merge(&self, key: &PullRequestKey) -> GithubResult<()>;
+
+    /// Adds the PR to its base branch's merge queue.
+    async fn enqueue_pull_request(
+        &self,
+        key: &PullRequestKey,
+        expected_head_sha: &str,
+    ) -> GithubResult<()>;
+
+    /// The GitHub Actions jobs whose latest check run on `head_sha` failed.
+    async fn failed_jobs(
+        &self,
+        key: &PullRequestKey,
+        head_sha: &str,
+    ) -> GithubResult<Vec<FailedJob>>;
+
+    /// Starts the failed jobs of a workflow run again.
+    async fn rerun_failed_jobs(&self, key: &PullRequestKey, run_id: u64) -> GithubResult<()>;
 
-    Ok(())
+    /// The tightest budget GitHub has reported so far, if any response carried one.
+    fn rate_limit(&self) -> Option<RateLimit>;
 }
 
-pub async fn needs_update_pr(key: &PullRequestKey, token: &str) -> GithubResult<PrStatus> {
-    let octocrab = Octocrab::builder()
-        .personal_token(token.to_owned())
-        .build()
-        .map_err(|err| GithubError::Client(Box::new(err)))?;
+pub struct OctocrabProvider {
+    client: Octocrab,
+    rate_limits: Mutex<HashMap<String, RateLimit>>,
+    etag_cache: Option<Storage>,
+}
 
-    let pr = octocrab
-        .pulls(&key.owner, &key.repo)
-        .get(key.pr_number)
-        .await
-        .map_err(|err| {
-            error!("Error: {err:?}");
-            GithubError::Request(Box::new(err))
-        })?;
+impl OctocrabProvider {
+    pub fn new(token: &str) -> GithubResult<Self> {
+        let client = Octocrab::builder()
+            .personal_token(token.to_owned())
+            .build()
+            .map_err(|err| GithubError::Client(Box::new(err)))?;
 
-    if pr.merged_at.is_some() {
-        log::info!("PR was merged, we not need to update the branch");
-        return Ok(PrStatus::Merged);
+        Ok(Self {
+            client,
+            rate_limits: Mutex::default(),
+            etag_cache: None,
+        })
+    }
+
+    /// Remembers ETags in `storage` so unchanged PRs are answered with a free
+    /// `304 Not Modified`, even across restarts. GraphQL has no ETags, so this
+    /// only covers the one-by-one REST lookups made when a batched query fails.
+    pub fn with_etag_cache(mut self, storage: Storage) -> Self {
+        self.etag_cache = Some(storage);
+        self
+    }
+
+    async fn send_json<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        route: &str,
+        body: Option<&Value>,
+    ) -> GithubResult<T> {
+        self.send(method, route, body, None)
+            .await?
+            .value
+            .ok_or(GithubError::UnexpectedStatus(StatusCode::NOT_MODIFIED))
+    }
+
+    /// Sends a request and decodes the JSON answer, keeping track of the rate
+    /// limit headers on the way. Requests against a spent budget fail without
+    /// reaching GitHub.
+    ///
+    /// With `etag` set the request is conditional, and `value` is `None` when
+    /// GitHub answers that nothing changed.
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        route: &str,
+        body: Option<&Value>,
+        etag: Option<EntityTag>,
+    ) -> GithubResult<Etagged<T>> {
+        let resource = if route == "/graphql" {
+            "graphql"
+        } else {
+            "core"
+        };
+        let now = Utc::now();
+        if let Some(reset_at) = self.throttled_until(resource, now) {
+            return Err(GithubError::RateLimited { reset_at });
+        }
+
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = etag {
+            EntityTag::insert_if_none_match_header(&mut headers, etag)
+                .map_err(|err| GithubError::Request(Box::new(err)))?;
+        }
+
+        let response = match method {
+            Method::POST => self.client._post(route, body).await,
+            Method::PUT => self.client._put(route, body).await,
+            _ => self.client._get_with_headers(route, Some(headers)).await,
+        }
+        .map_err(|err| GithubError::Request(Box::new(err)))?;
+
+        if let Some(rate_limit) = RateLimit::from_headers(response.headers()) {
+            self.record_rate_limit(rate_limit);
+        }
+        if let Some(reset_at) = retry_at(response.status(), response.headers(), now) {
+            let reset_at = self.throttle(resource, reset_at);
+            return Err(GithubError::RateLimited { reset_at });
+        }
+
+        let etag = EntityTag::extract_from_response(&response);
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Etagged { etag, value: None });
+        }
+
+        let response = octocrab::map_github_error(response)
+            .await
+            .map_err(|err| match err {
+                octocrab::Error::GitHub { ref source, .. }
+                    if source.message.to_lowercase().contains("rate limit") =>
+                {
+                    let reset_at =
+                        self.throttle(resource, now + TimeDelta::seconds(DEFAULT_RETRY_AFTER_SECS));
+                    GithubError::RateLimited { reset_at }
+                }
+                err => GithubError::Request(Box::new(err)),
+            })?;
+        let body = self
+            .client
+            .body_to_string(response)
+            .await
+            .map_err(|err| GithubError::Request(Box::new(err)))?;
+
+        Ok(Etagged {
+            etag,
+            value: Some(serde_json::from_str(&body)?),
+        })
+    }
+
+    fn record_rate_limit(&self, rate_limit: RateLimit) {
+        self.rate_limits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(rate_limit.resource.clone(), rate_limit);
+    }
+
+    /// Marks the budget as spent until at least `reset_at` and returns when it resets.
+    fn throttle(&self, resource: &str, reset_at: DateTime<Utc>) -> DateTime<Utc> {
+        warn!("GitHub rate limit reached for {resource}, waiting until {reset_at}");
+        let mut rate_limits = self
+            .rate_limits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let rate_limit = rate_limits
+            .entry(resource.to_owned())
+            .or_insert_with(|| RateLimit {
+                resource: resource.to_owned(),
+                limit: 0,
+                remaining: 0,
+                reset_at,
+            });
+        rate_limit.remaining = 0;
+        rate_limit.reset_at = rate_limit.reset_at.max(reset_at);
+        rate_limit.reset_at
+    }
+
+    fn throttled_until(&self, resource: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let rate_limits = self
+            .rate_limits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        rate_limits
+            .get(resource)
+            .filter(|rate_limit| rate_limit.remaining == 0 && rate_limit.reset_at > now)
+            .map(|rate_limit| rate_limit.reset_at)
+    }
+
+    async fn get_pull_request(&self, key: &PullRequestKey) -> GithubResult<PullRequest> {
+        let route = format!("/repos/{}/{}/pulls/{}", key.owner, key.repo, key.pr_number);
+        self.send_json(Method::GET, &route, None).await
+    }
+
+    async fn rest_snapshot(&self, key: &PullRequestKey) -> GithubResult<PullRequestSnapshot> {
+        let cached = self.cached_pull_request(key).await;
+        let route = format!("/repos/{}/{}/pulls/{}", key.owner, key.repo, key.pr_number);
+        let etag = cached
+            .as_ref()
+            .and_then(|cached| cached.etag.parse::<EntityTag>().ok());
+
+        let response = self
+            .send::<PullRequest>(Method::GET, &route, None, etag)
+            .await
+            .inspect_err(|err| {
+                error!("Error: {err:?}");
+            })?;
+
+        let (Some(pr), etag) = (response.value, response.etag) else {
+            debug!(
+                "{}/{}#{} is unchanged, reusing the cached status",
+                key.owner, key.repo, key.pr_number
+            );
+            return cached
+                .map(|cached| cached.snapshot)
+                .ok_or(GithubError::PullRequestDetailsUnavailable);
+        };
+
+        let snapshot = snapshot_from_rest(pr);
+        if let (Some(storage), Some(etag)) = (&self.etag_cache, etag) {
+            let cached = CachedPullRequest {
+                etag: etag.to_string(),
+                snapshot: snapshot.clone(),
+            };
+            if let Err(err) = storage.set_cached_pull_request(key.clone(), cached).await {
+                warn!(
+                    "Failed to cache the ETag for {}/{}#{}: {err}",
+                    key.owner, key.repo, key.pr_number
+                );
+            }
+        }
+
+        Ok(snapshot)
+    }
+
+    async fn fetch_batches(
+        &self,
+        keys: &[PullRequestKey],
+        max_concurrent: usize,
+    ) -> Vec<GithubResult<PullRequestSnapshot>> {
+        let mut snapshots = Vec::with_capacity(keys.len());
+
+        for chunk in keys.chunks(SNAPSHOT_BATCH_SIZE) {
+            let query = build_snapshot_query(chunk);
+            match self
+                .send_json::<Value>(Method::POST, "/graphql", Some(&query))
+                .await
+            {
+                Ok(response) if is_rate_limited(&response) => {
+                    let reset_at = self.throttle(
+                        "graphql",
+                        Utc::now() + TimeDelta::seconds(DEFAULT_RETRY_AFTER_SECS),
+                    );
+                    snapshots.extend(
+                        chunk
+                            .iter()
+                            .map(|_| Err(GithubError::RateLimited { reset_at })),
+                    );
+                }
+                Ok(response) => snapshots.extend(parse_snapshot_response(chunk, &response)),
+                Err(GithubError::RateLimited { reset_at }) => {
+                    snapshots.extend(
+                        chunk
+                            .iter()
+                            .map(|_| Err(GithubError::RateLimited { reset_at })),
+                    );
+                }
+                Err(err) => {
+                    warn!("Batched status query failed, checking PRs one by one: {err}");
+                    let requests: Vec<_> =
+                        chunk.iter().map(|key| self.rest_snapshot(key)).collect();
+                    let fallback: Vec<_> = stream::iter(requests)
+                        .buffered(max_concurrent)
+                        .collect()
+                        .await;
+                    snapshots.extend(fallback);
+                }
+            }
+        }
+
+        snapshots
+    }
+
+    /// Merges the base branch in through the REST endpoint.
+    async fn merge_base_branch(
+        &self,
+        key: &PullRequestKey,
+        expected_head_sha: &str,
+    ) -> GithubResult<()> {
+        let route = format!(
+            "/repos/{}/{}/pulls/{}/update-branch",
+            key.owner, key.repo, key.pr_number
+        );
+        let body = json!({ "expected_head_sha": expected_head_sha });
+        self.send_json::<Value>(Method::PUT, &route, Some(&body))
+            .await
+            .map(|_| ())
+            .map_err(|err| match err {
+                GithubError::Request(ref source) if is_head_mismatch(source) => {
+                    GithubError::HeadMoved
+                }
+                err => err,
+            })
+    }
+
+    /// The PR's GraphQL node id, which mutations take instead of its number.
+    async fn pull_request_id(&self, key: &PullRequestKey) -> GithubResult<String> {
+        let query = json!({
+            "query": "query($owner: String!, $repo: String!, $number: Int!) {\n  \
+                      repository(owner: $owner, name: $repo) { pullRequest(number: $number) { id } }\n}",
+            "variables": { "owner": key.owner, "repo": key.repo, "number": key.pr_number },
+        });
+        let response = self.graphql(&query).await?;
+        response["data"]["repository"]["pullRequest"]["id"]
+            .as_str()
+            .map(str::to_owned)
+            .ok_or(GithubError::PullRequestDetailsUnavailable)
+    }
+
+    /// Rebases the branch onto its base. REST can only merge, so this goes
+    /// through GraphQL.
+    async fn rebase_branch(
+        &self,
+        key: &PullRequestKey,
+        expected_head_sha: &str,
+    ) -> GithubResult<()> {
+        let pull_request_id = self.pull_request_id(key).await?;
+        let mutation = json!({
+            "query": "mutation($id: ID!, $head: GitObjectID!) {\n  \
+                      updatePullRequestBranch(input: { pullRequestId: $id, expectedHeadOid: $head, \
+                      updateMethod: REBASE }) { clientMutationId }\n}",
+            "variables": { "id": pull_request_id, "head": expected_head_sha },
+        });
+        self.graphql(&mutation).await?;
+
+        Ok(())
+    }
+
+    /// Posts a GraphQL request and turns errors reported in the body into failures.
+    async fn graphql(&self, query: &Value) -> GithubResult<Value> {
+        let response = self
+            .send_json::<Value>(Method::POST, "/graphql", Some(query))
+            .await?;
+        if is_rate_limited(&response) {
+            let reset_at = self.throttle(
+                "graphql",
+                Utc::now() + TimeDelta::seconds(DEFAULT_RETRY_AFTER_SECS),
+            );
+            return Err(GithubError::RateLimited { reset_at });
+        }
+
+        // Mutations given an `expectedHeadOid` the branch has moved past fail as stale data.
+        let error = &response["errors"][0];
+        match error["message"].as_str() {
+            Some(_) if error["type"] == "STALE_DATA" => Err(GithubError::HeadMoved),
+            Some(message) => Err(GithubError::Graphql(message.to_owned())),
+            None => Ok(response),
+        }
+    }
+
+    async fn cached_pull_request(&self, key: &PullRequestKey) -> Option<CachedPullRequest> {
+        let storage = self.etag_cache.as_ref()?;
+        storage
+            .get_cached_pull_request(key.clone())
+            .await
+            .unwrap_or_else(|err| {
+                warn!("Failed to read the cached response: {err}");
+                None
+            })
+    }
+}
+
+fn snapshot_from_rest(pr: PullRequest) -> PullRequestSnapshot {
+    let state = state_from_rest(&pr);
+    let head_sha = pr.head.sha.clone();
+    let base_ref = pr.base.ref_field.clone();
+
+    match state {
+        PullRequestState::Merged => {
+            log::info!("PR was merged, we not need to update the branch");
+            return PullRequestSnapshot {
+                title: pr.title.unwrap_or_default(),
+                state,
+                status: PrStatus::Merged,
+                head_sha,
+                base_ref,
+                auto_merge_enabled: None,
+                merge_queue: None,
+                approved: None,
+            };
+        }
+        PullRequestState::Closed => {
+            return PullRequestSnapshot {
+                title: pr.title.unwrap_or_default(),
+                state,
+                status: PrStatus::Closed,
+                head_sha,
+                base_ref,
+                auto_merge_enabled: None,
+                merge_queue: None,
+                approved: None,
+            };
+        }
+        PullRequestState::Open => {}
     }
 
     let status = match pr.mergeable_state {
@@ -69,54 +579,914 @@ pub async fn needs_update_pr(key: &PullRequestKey, token: &str) -> GithubResult<
         Some(MergeableState::Clean) => PrStatus::UpToDate,
         Some(MergeableState::Dirty) => PrStatus::Conflicts,
         Some(MergeableState::Blocked) => PrStatus::Blocked,
-        Some(MergeableState::Unknown | MergeableState::Unstable) | None => PrStatus::Unknown,
+        Some(MergeableState::Unknown) | None => PrStatus::Pending,
         Some(_) => PrStatus::Unknown,
     };
 
-    Ok(status)
+    PullRequestSnapshot {
+        title: pr.title.unwrap_or_default(),
+        state,
+        status,
+        head_sha,
+        base_ref,
+        auto_merge_enabled: None,
+        merge_queue: None,
+        approved: None,
+    }
+}
+
+fn state_from_rest(pr: &PullRequest) -> PullRequestState {
+    if pr.merged_at.is_some() {
+        return PullRequestState::Merged;
+    }
+
+    match pr.state {
+        Some(octocrab::models::IssueState::Closed) => PullRequestState::Closed,
+        _ => PullRequestState::Open,
+    }
+}
+
+/// Builds one query that aliases every PR as `pr{index}`, passing the
+/// identities as variables so owner and repo names never need escaping.
+fn build_snapshot_query(keys: &[PullRequestKey]) -> Value {
+    let mut parameters = Vec::with_capacity(keys.len());
+    let mut selections = String::new();
+    let mut variables = Map::new();
+
+    for (index, key) in keys.iter().enumerate() {
+        parameters.push(format!(
+            "$owner{index}: String!, $repo{index}: String!, $number{index}: Int!"
+        ));
+        selections.push_str(&format!(
+            "  pr{index}: repository(owner: $owner{index}, name: $repo{index}) {{\n    \
+             pullRequest(number: $number{index}) {{ ...snapshot }}\n  }}\n"
+        ));
+        variables.insert(format!("owner{index}"), json!(key.owner));
+        variables.insert(format!("repo{index}"), json!(key.repo));
+        variables.insert(format!("number{index}"), json!(key.pr_number));
+    }
+
+    let query = format!(
+        "query({}) {{\n{selections}}}\n\
+         fragment snapshot on PullRequest {{ title state merged mergeStateStatus headRefOid baseRefName \
+         autoMergeRequest {{ mergeMethod }} isInMergeQueue isMergeQueueEnabled reviewDecision }}",
+        parameters.join(", ")
+    );
+
+    json!({ "query": query, "variables": variables })
+}
+
+/// GraphQL reports a spent budget as a `RATE_LIMITED` error on a 200 response.
+fn is_rate_limited(response: &Value) -> bool {
+    response["errors"]
+        .as_array()
+        .is_some_and(|errors| errors.iter().any(|error| error["type"] == "RATE_LIMITED"))
+}
+
+fn parse_snapshot_response(
+    keys: &[PullRequestKey],
+    response: &Value,
+) -> Vec<GithubResult<PullRequestSnapshot>> {
+    let errors = response["errors"].as_array().cloned().unwrap_or_default();
+
+    keys.iter()
+        .enumerate()
+        .map(|(index, key)| {
+            let alias = format!("pr{index}");
+            let pull_request = &response["data"][&alias]["pullRequest"];
+            if pull_request.is_null() {
+                let message = errors
+                    .iter()
+                    .find(|error| error["path"][0] == alias.as_str())
+                    .and_then(|error| error["message"].as_str())
+                    .unwrap_or("pull request not found");
+                warn!(
+                    "No status for {}/{}#{}: {message}",
+                    key.owner, key.repo, key.pr_number
+                );
+                return Err(GithubError::Graphql(message.to_owned()));
+            }
+
+            let pull_request = GraphqlPullRequest::deserialize(pull_request)
+                .map_err(|err| GithubError::Graphql(err.to_string()))?;
+
+            let state = match pull_request.state.as_str() {
+                "OPEN" => PullRequestState::Open,
+                "MERGED" => PullRequestState::Merged,
+                _ => PullRequestState::Closed,
+            };
+
+            Ok(PullRequestSnapshot {
+                status: match state {
+                    PullRequestState::Closed => PrStatus::Closed,
+                    PullRequestState::Open if pull_request.is_in_merge_queue => PrStatus::Queued,
+                    _ => status_from_merge_state(
+                        pull_request.merged,
+                        &pull_request.merge_state_status,
+                    ),
+                },
+                state,
+                title: pull_request.title,
+                head_sha: pull_request.head_ref_oid,
+                base_ref: pull_request.base_ref_name,
+                auto_merge_enabled: Some(pull_request.auto_merge_request.is_some()),
+                merge_queue: Some(pull_request.is_merge_queue_enabled),
+                approved: Some(matches!(
+                    pull_request.review_decision.as_deref(),
+                    None | Some("APPROVED")
+                )),
+            })
+        })
+        .collect()
+}
+
+/// When a throttled response says we may try again, or `None` if it was not throttled.
+///
+/// Secondary limits that come without any header are caught from the error
+/// message in [`OctocrabProvider::send`].
+fn retry_at(status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if status != StatusCode::FORBIDDEN && status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    if let Some(seconds) = header_number(headers, "retry-after") {
+        return Some(now + TimeDelta::seconds(seconds.try_into().ok()?));
+    }
+
+    match RateLimit::from_headers(headers) {
+        Some(rate_limit) if rate_limit.remaining == 0 => Some(rate_limit.reset_at),
+        _ if status == StatusCode::TOO_MANY_REQUESTS => {
+            Some(now + TimeDelta::seconds(DEFAULT_RETRY_AFTER_SECS))
+        }
+        _ => None,
+    }
+}
+
+/// A failure anywhere wins over anything still running, which wins over a pass.
+fn combine_ci_state(status: &CombinedStatus, check_runs: &[CheckRun]) -> CiState {
+    let mut states = Vec::with_capacity(check_runs.len() + 1);
+    if status.total_count > 0 {
+        states.push(match status.state.as_str() {
+            "success" => CiState::Passing,
+            "pending" => CiState::Pending,
+            _ => CiState::Failing,
+        });
+    }
+    states.extend(check_runs.iter().map(|run| {
+        if run.status != "completed" {
+            return CiState::Pending;
+        }
+        match run.conclusion.as_deref() {
+            Some("success" | "neutral" | "skipped") => CiState::Passing,
+            _ => CiState::Failing,
+        }
+    }));
+
+    [CiState::Failing, CiState::Pending, CiState::Passing]
+        .into_iter()
+        .find(|state| states.contains(state))
+        .unwrap_or(CiState::Missing)
+}
+
+/// Failed or timed out check runs made by GitHub Actions, with the run they
+/// belong to read from their details link.
+fn failed_actions_jobs(check_runs: Vec<CheckRun>) -> Vec<FailedJob> {
+    check_runs
+        .into_iter()
+        .filter(|run| {
+            run.app
+                .as_ref()
+                .is_some_and(|app| app.slug == "github-actions")
+        })
+        .filter(|run| matches!(run.conclusion.as_deref(), Some("failure" | "timed_out")))
+        .filter_map(|run| {
+            let run_id = ACTIONS_RUN_URL_REGEX
+                .captures(run.details_url.as_deref()?)?
+                .get(1)?
+                .as_str()
+                .parse()
+                .ok()?;
+            Some(FailedJob {
+                name: run.name,
+                run_id,
+            })
+        })
+        .collect()
+}
+
+/// GitHub refused a branch update because the head is no longer the expected
+/// SHA. Merge conflicts and branches with nothing to update are refused with a
+/// 422 as well, so the message tells them apart.
+fn is_head_mismatch(err: &octocrab::Error) -> bool {
+    matches!(
+        err,
+        octocrab::Error::GitHub { source, .. }
+            if source.status_code == StatusCode::UNPROCESSABLE_ENTITY
+                && source.message.to_lowercase().contains("expected head sha")
+    )
+}
+
+fn header_str<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok()
+}
+
+fn header_number(headers: &HeaderMap, name: &str) -> Option<u64> {
+    header_str(headers, name)?.trim().parse().ok()
+}
+
+fn status_from_merge_state(merged: bool, merge_state_status: &str) -> PrStatus {
+    if merged {
+        return PrStatus::Merged;
+    }
+
+    match merge_state_status {
+        "BEHIND" => PrStatus::Behind,
+        "CLEAN" => PrStatus::UpToDate,
+        "DIRTY" => PrStatus::Conflicts,
+        "BLOCKED" => PrStatus::Blocked,
+        "UNKNOWN" => PrStatus::Pending,
+        _ => PrStatus::Unknown,
+    }
+}
+
+/// Re-polls the PRs GitHub is still computing a merge state for, backing off
+/// between rounds. Anything still computing after the last round stays `Pending`.
+async fn resolve_pending<F, Fut>(
+    keys: &[PullRequestKey],
+    mut snapshots: Vec<GithubResult<PullRequestSnapshot>>,
+    mut fetch: F,
+) -> Vec<GithubResult<PullRequestSnapshot>>
+where
+    F: FnMut(Vec<PullRequestKey>) -> Fut,
+    Fut: Future<Output = Vec<GithubResult<PullRequestSnapshot>>>,
+{
+    for delay in MERGE_STATE_RETRY_DELAYS {
+        let pending: Vec<usize> = snapshots
+            .iter()
+            .enumerate()
+            .filter(|(_, snapshot)| {
+                matches!(snapshot, Ok(snapshot) if snapshot.status == PrStatus::Pending)
+            })
+            .map(|(index, _)| index)
+            .collect();
+        if pending.is_empty() {
+            break;
+        }
+
+        debug!(
+            "GitHub is still computing {} merge states, asking again in {delay:?}",
+            pending.len()
+        );
+        tokio::time::sleep(delay).await;
+
+        let retried = fetch(pending.iter().map(|&index| keys[index].clone()).collect()).await;
+        for (index, snapshot) in pending.into_iter().zip(retried) {
+            // A failed re-poll keeps the earlier answer rather than turning into an error.
+            if snapshot.is_ok() {
+                snapshots[index] = snapshot;
+            }
+        }
+    }
+
+    snapshots
+}
+
+/// Keeps one authenticated client alive so its connection pool is reused across
+/// monitor cycles. It is only rebuilt when the token changes.
+#[derive(Clone, Default)]
+pub struct GithubClients {
+    cached: Arc<Mutex<Option<CachedProvider>>>,
+    etag_cache: Option<Storage>,
+}
+
+struct CachedProvider {
+    token: String,
+    provider: Arc<OctocrabProvider>,
 }
 
-pub async fn get_pr_details(token: &str, key: &PullRequestKey) -> GithubResult<PullRequestModel> {
-    let octocrab = Octocrab::builder()
-        .personal_token(token.to_owned())
-        .build()
-        .map_err(|err| GithubError::Client(Box::new(err)))?;
+impl GithubClients {
+    pub fn new(etag_cache: Storage) -> Self {
+        Self {
+            cached: Arc::default(),
+            etag_cache: Some(etag_cache),
+        }
+    }
+
+    pub fn provider(&self, token: &str) -> GithubResult<Arc<OctocrabProvider>> {
+        let mut cached = self.cached.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(cached) = cached.as_ref().filter(|cached| cached.token == token) {
+            return Ok(Arc::clone(&cached.provider));
+        }
+
+        let mut provider = OctocrabProvider::new(token)?;
+        if let Some(storage) = &self.etag_cache {
+            provider = provider.with_etag_cache(storage.clone());
+        }
+        let provider = Arc::new(provider);
+        *cached = Some(CachedProvider {
+            token: token.to_owned(),
+            provider: Arc::clone(&provider),
+        });
+
+        Ok(provider)
+    }
+
+    pub fn invalidate(&self) {
+        *self.cached.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+}
+
+#[async_trait]
+impl PullRequestProvider for OctocrabProvider {
+    async fn update_pr_branch(
+        &self,
+        key: &PullRequestKey,
+        method: UpdateMethod,
+        expected_head_sha: &str,
+    ) -> GithubResult<()> {
+        let result = match method {
+            UpdateMethod::Merge => self.merge_base_branch(key, expected_head_sha).await,
+            UpdateMethod::Rebase => self.rebase_branch(key, expected_head_sha).await,
+        };
+
+        result.map_err(|err| {
+            error!("Error: {err:?}");
+            match err {
+                GithubError::RateLimited { .. } | GithubError::HeadMoved => err,
+                _ => GithubError::UpdateBranchUnavailable,
+            }
+        })
+    }
+
+    async fn behind_by(
+        &self,
+        key: &PullRequestKey,
+        base_ref: &str,
+        head_sha: &str,
+    ) -> GithubResult<u32> {
+        // Only the counts are needed, so the commit list is kept to one page of one.
+        let route = format!(
+            "/repos/{}/{}/compare/{base_ref}...{head_sha}?per_page=1",
+            key.owner, key.repo
+        );
+        let comparison: Comparison = self.send_json(Method::GET, &route, None).await?;
+
+        Ok(comparison.behind_by)
+    }
+
+    async fn failed_jobs(
+        &self,
+        key: &PullRequestKey,
+        head_sha: &str,
+    ) -> GithubResult<Vec<FailedJob>> {
+        let route = format!(
+            "/repos/{}/{}/commits/{head_sha}/check-runs?per_page=100",
+            key.owner, key.repo
+        );
+        let checks: CheckRuns = self.send_json(Method::GET, &route, None).await?;
+
+        Ok(failed_actions_jobs(checks.check_runs))
+    }
+
+    async fn rerun_failed_jobs(&self, key: &PullRequestKey, run_id: u64) -> GithubResult<()> {
+        let route = format!(
+            "/repos/{}/{}/actions/runs/{run_id}/rerun-failed-jobs",
+            key.owner, key.repo
+        );
+        self.send_json::<Value>(Method::POST, &route, None)
+            .await
+            .map(|_| ())
+    }
+
+    async fn ci_state(&self, key: &PullRequestKey, head_sha: &str) -> GithubResult<CiState> {
+        let commit = format!("/repos/{}/{}/commits/{head_sha}", key.owner, key.repo);
+        let status: CombinedStatus = self
+            .send_json(Method::GET, &format!("{commit}/status"), None)
+            .await?;
+        let checks: CheckRuns = self
+            .send_json(
+                Method::GET,
+                &format!("{commit}/check-runs?per_page=100"),
+                None,
+            )
+            .await?;
+
+        Ok(combine_ci_state(&status, &checks.check_runs))
+    }
+
+    async fn enable_auto_merge(
+        &self,
+        key: &PullRequestKey,
+        method: MergeMethod,
+        expected_head_sha: &str,
+    ) -> GithubResult<()> {
+        let pull_request_id = self.pull_request_id(key).await?;
+        let mutation = json!({
+            "query": "mutation($id: ID!, $head: GitObjectID!, $method: PullRequestMergeMethod!) {\n  \
+                      enablePullRequestAutoMerge(input: { pullRequestId: $id, expectedHeadOid: $head, \
+                      mergeMethod: $method }) { clientMutationId }\n}",
+            "variables": { "id": pull_request_id, "head": expected_head_sha, "method": method.as_graphql() },
+        });
+        self.graphql(&mutation).await?;
+
+        Ok(())
+    }
+
+    async fn disable_auto_merge(&self, key: &PullRequestKey) -> GithubResult<()> {
+        let pull_request_id = self.pull_request_id(key).await?;
+        let mutation = json!({
+            "query": "mutation($id: ID!) {\n  \
+                      disablePullRequestAutoMerge(input: { pullRequestId: $id }) { clientMutationId }\n}",
+            "variables": { "id": pull_request_id },
+        });
+        self.graphql(&mutation).await?;
+
+        Ok(())
+    }
+
+    async fn enqueue_pull_request(
+        &self,
+        key: &PullRequestKey,
+        expected_head_sha: &str,
+    ) -> GithubResult<()> {
+        let pull_request_id = self.pull_request_id(key).await?;
+        let mutation = json!({
+            "query": "mutation($id: ID!, $head: GitObjectID!) {\n  \
+                      enqueuePullRequest(input: { pullRequestId: $id, expectedHeadOid: $head }) \
+                      { clientMutationId }\n}",
+            "variables": { "id": pull_request_id, "head": expected_head_sha },
+        });
+        self.graphql(&mutation).await?;
 
-    let pr = octocrab
-        .pulls(&key.owner, &key.repo)
-        .get(key.pr_number)
+        Ok(())
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        let rate_limits = self
+            .rate_limits
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        rate_limits
+            .values()
+            .min_by_key(|rate_limit| rate_limit.remaining)
+            .cloned()
+    }
+
+    async fn fetch_snapshots(
+        &self,
+        keys: &[PullRequestKey],
+        max_concurrent: usize,
+    ) -> Vec<GithubResult<PullRequestSnapshot>> {
+        let snapshots = self.fetch_batches(keys, max_concurrent).await;
+        resolve_pending(keys, snapshots, |keys| async move {
+            self.fetch_batches(&keys, max_concurrent).await
+        })
         .await
-        .map_err(|err| {
+    }
+
+    async fn get_pr_details(&self, key: &PullRequestKey) -> GithubResult<PullRequestModel> {
+        let pr = self.get_pull_request(key).await.map_err(|err| {
             error!("Error: {err:?}");
-            GithubError::PullRequestDetailsUnavailable
+            match err {
+                GithubError::RateLimited { .. } => err,
+                _ => GithubError::PullRequestDetailsUnavailable,
+            }
         })?;
 
-    if pr.mergeable_state.is_none() {
-        return Err(GithubError::PullRequestDetailsUnavailable);
+        let state = state_from_rest(&pr);
+
+        Ok(PullRequestModel {
+            owner: key.owner.clone(),
+            repo: key.repo.clone(),
+            pr_number: key.pr_number,
+            title: pr.title.unwrap_or_default(),
+            state,
+            closed_at: pr.closed_at,
+            url: pr.url.to_string(),
+            last_error: None,
+            last_status: None,
+            last_checked_at: None,
+            added_at: None,
+            merged_at: pr.merged_at,
+            status_changed_at: None,
+            last_notified_at: None,
+            mode: MonitorMode::default(),
+            update_method: None,
+            behind_by: None,
+            auto_merge: None,
+            auto_merge_enabled: false,
+        })
     }
+}
 
-    let state = match pr.state {
-        Some(octocrab::models::IssueState::Open) => PullRequestState::Open,
-        Some(octocrab::models::IssueState::Closed) => PullRequestState::Closed,
-        _ => PullRequestState::Open,
-    };
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::sync::Mutex;
 
-    Ok(PullRequestModel {
-        owner: key.owner.clone(),
-        repo: key.repo.clone(),
-        pr_number: key.pr_number,
-        title: pr.title.unwrap_or_default(),
-        state,
-        closed_at: pr
-            .closed_at
-            .map(|closed_at| closed_at.format("%d/%m/%Y %H:%M").to_string()),
-        url: pr.url.to_string(),
-    })
+    /// Replays scripted GitHub answers so monitor flows can be tested offline.
+    ///
+    /// Each PR answers with its queued statuses in order and then keeps repeating
+    /// the last one.
+    #[derive(Default)]
+    pub struct ScriptedProvider {
+        statuses: Mutex<HashMap<PullRequestKey, VecDeque<PrStatus>>>,
+        failing_updates: Mutex<HashSet<PullRequestKey>>,
+        updated_branches: Mutex<Vec<PullRequestKey>>,
+        update_methods: Mutex<HashMap<PullRequestKey, UpdateMethod>>,
+        moved_heads: Mutex<HashSet<PullRequestKey>>,
+        ci_states: Mutex<HashMap<PullRequestKey, CiState>>,
+        unreadable_ci: Mutex<HashSet<PullRequestKey>>,
+        ci_requests: Mutex<usize>,
+        behind_counts: Mutex<HashMap<PullRequestKey, u32>>,
+        failing_compares: Mutex<HashSet<PullRequestKey>>,
+        auto_merges: Mutex<HashMap<PullRequestKey, MergeMethod>>,
+        merge_queues: Mutex<HashSet<PullRequestKey>>,
+        unapproved: Mutex<HashSet<PullRequestKey>>,
+        failing_enqueues: Mutex<HashSet<PullRequestKey>>,
+        enqueued: Mutex<Vec<PullRequestKey>>,
+        failed_jobs: Mutex<HashMap<PullRequestKey, Vec<FailedJob>>>,
+        failing_reruns: Mutex<HashSet<u64>>,
+        reruns: Mutex<Vec<(PullRequestKey, u64)>>,
+        snapshot_requests: Mutex<usize>,
+        rate_limit: Mutex<Option<RateLimit>>,
+        updates_in_flight: Mutex<(usize, usize)>,
+    }
+
+    impl ScriptedProvider {
+        pub fn with_statuses(
+            self,
+            key: &PullRequestKey,
+            statuses: impl IntoIterator<Item = PrStatus>,
+        ) -> Self {
+            self.statuses
+                .lock()
+                .unwrap()
+                .insert(key.clone(), statuses.into_iter().collect());
+            self
+        }
+
+        pub fn with_failing_updates(self, key: &PullRequestKey) -> Self {
+            self.failing_updates.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        /// Pushes to the branch right after its next check, so the update that
+        /// follows finds a different head than the one that was checked.
+        pub fn with_moved_head(self, key: &PullRequestKey) -> Self {
+            self.moved_heads.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        /// PRs without a scripted count are one commit behind.
+        pub fn with_behind_by(self, key: &PullRequestKey, commits: u32) -> Self {
+            self.behind_counts
+                .lock()
+                .unwrap()
+                .insert(key.clone(), commits);
+            self
+        }
+
+        pub fn with_failing_compare(self, key: &PullRequestKey) -> Self {
+            self.failing_compares.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        /// Makes the PR's base branch merge through a merge queue.
+        pub fn with_merge_queue(self, key: &PullRequestKey) -> Self {
+            self.merge_queues.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        /// PRs are approved unless they are marked as waiting for a review here.
+        pub fn with_unapproved(self, key: &PullRequestKey) -> Self {
+            self.unapproved.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        /// Makes the merge queue refuse the PR.
+        pub fn with_failing_enqueue(self, key: &PullRequestKey) -> Self {
+            self.failing_enqueues.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        /// The jobs keep failing however often they are re-run.
+        pub fn with_failed_job(self, key: &PullRequestKey, name: &str, run_id: u64) -> Self {
+            self.failed_jobs
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_default()
+                .push(FailedJob {
+                    name: name.to_owned(),
+                    run_id,
+                });
+            self
+        }
+
+        /// Makes GitHub refuse to re-run the workflow run.
+        pub fn with_failing_rerun(self, run_id: u64) -> Self {
+            self.failing_reruns.lock().unwrap().insert(run_id);
+            self
+        }
+
+        /// PRs without a scripted CI state report [`CiState::Missing`].
+        pub fn with_ci_state(self, key: &PullRequestKey, state: CiState) -> Self {
+            self.ci_states.lock().unwrap().insert(key.clone(), state);
+            self
+        }
+
+        /// Answers CI and failed job lookups for the PR with an error, like a
+        /// token without `checks:read`.
+        pub fn with_unreadable_ci(self, key: &PullRequestKey) -> Self {
+            self.unreadable_ci.lock().unwrap().insert(key.clone());
+            self
+        }
+
+        pub fn with_rate_limit(self, rate_limit: RateLimit) -> Self {
+            *self.rate_limit.lock().unwrap() = Some(rate_limit);
+            self
+        }
+
+        pub fn updated_branches(&self) -> Vec<PullRequestKey> {
+            self.updated_branches.lock().unwrap().clone()
+        }
+
+        /// PRs that currently have auto-merge on, with its merge method.
+        pub fn auto_merges(&self) -> HashMap<PullRequestKey, MergeMethod> {
+            self.auto_merges.lock().unwrap().clone()
+        }
+
+        pub fn enqueued(&self) -> Vec<PullRequestKey> {
+            self.enqueued.lock().unwrap().clone()
+        }
+
+        pub fn reruns(&self) -> Vec<(PullRequestKey, u64)> {
+            self.reruns.lock().unwrap().clone()
+        }
+
+        /// The method each PR's branch was last updated with.
+        pub fn update_methods(&self) -> HashMap<PullRequestKey, UpdateMethod> {
+            self.update_methods.lock().unwrap().clone()
+        }
+
+        fn next_status(&self, key: &PullRequestKey) -> GithubResult<PrStatus> {
+            let mut statuses = self.statuses.lock().unwrap();
+            let queue = statuses
+                .get_mut(key)
+                .ok_or(GithubError::PullRequestDetailsUnavailable)?;
+            let status = if queue.len() > 1 {
+                queue.pop_front()
+            } else {
+                queue.front().copied()
+            };
+
+            status.ok_or(GithubError::PullRequestDetailsUnavailable)
+        }
+
+        /// Moves on each time the branch is updated, like a new merge commit would.
+        fn head_sha(&self, key: &PullRequestKey) -> String {
+            let updates = self
+                .updated_branches
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|updated| *updated == key)
+                .count();
+            format!("sha-{updates}")
+        }
+
+        pub fn snapshot_requests(&self) -> usize {
+            *self.snapshot_requests.lock().unwrap()
+        }
+
+        pub fn ci_requests(&self) -> usize {
+            *self.ci_requests.lock().unwrap()
+        }
+
+        /// The most branch updates that were ever running at the same time.
+        pub fn peak_concurrent_updates(&self) -> usize {
+            self.updates_in_flight.lock().unwrap().1
+        }
+    }
+
+    #[async_trait]
+    impl PullRequestProvider for ScriptedProvider {
+        async fn get_pr_details(&self, key: &PullRequestKey) -> GithubResult<PullRequestModel> {
+            Ok(PullRequestModel {
+                owner: key.owner.clone(),
+                repo: key.repo.clone(),
+                pr_number: key.pr_number,
+                title: format!("{}/{}#{}", key.owner, key.repo, key.pr_number),
+                state: PullRequestState::Open,
+                closed_at: None,
+                url: format!(
+                    "https://api.github.com/repos/{}/{}/pulls/{}",
+                    key.owner, key.repo, key.pr_number
+                ),
+                last_error: None,
+                last_status: None,
+                last_checked_at: None,
+                added_at: None,
+                merged_at: None,
+                status_changed_at: None,
+                last_notified_at: None,
+                mode: MonitorMode::default(),
+                update_method: None,
+                behind_by: None,
+                auto_merge: None,
+                auto_merge_enabled: false,
+            })
+        }
+
+        async fn fetch_snapshots(
+            &self,
+            keys: &[PullRequestKey],
+            _max_concurrent: usize,
+        ) -> Vec<GithubResult<PullRequestSnapshot>> {
+            *self.snapshot_requests.lock().unwrap() += 1;
+
+            let mut snapshots = Vec::with_capacity(keys.len());
+            for key in keys {
+                snapshots.push(self.next_status(key).map(|status| PullRequestSnapshot {
+                    title: format!("{}/{}#{}", key.owner, key.repo, key.pr_number),
+                    state: match status {
+                        PrStatus::Merged => PullRequestState::Merged,
+                        PrStatus::Closed => PullRequestState::Closed,
+                        _ => PullRequestState::Open,
+                    },
+                    status,
+                    head_sha: self.head_sha(key),
+                    base_ref: "main".to_owned(),
+                    auto_merge_enabled: Some(self.auto_merges.lock().unwrap().contains_key(key)),
+                    merge_queue: Some(self.merge_queues.lock().unwrap().contains(key)),
+                    approved: Some(!self.unapproved.lock().unwrap().contains(key)),
+                }));
+            }
+
+            snapshots
+        }
+
+        async fn behind_by(
+            &self,
+            key: &PullRequestKey,
+            _base_ref: &str,
+            _head_sha: &str,
+        ) -> GithubResult<u32> {
+            if self.failing_compares.lock().unwrap().contains(key) {
+                return Err(GithubError::UnexpectedStatus(StatusCode::NOT_FOUND));
+            }
+            Ok(self
+                .behind_counts
+                .lock()
+                .unwrap()
+                .get(key)
+                .copied()
+                .unwrap_or(1))
+        }
+
+        async fn ci_state(&self, key: &PullRequestKey, _head_sha: &str) -> GithubResult<CiState> {
+            *self.ci_requests.lock().unwrap() += 1;
+            if self.unreadable_ci.lock().unwrap().contains(key) {
+                return Err(GithubError::UnexpectedStatus(StatusCode::FORBIDDEN));
+            }
+            Ok(self
+                .ci_states
+                .lock()
+                .unwrap()
+                .get(key)
+                .copied()
+                .unwrap_or(CiState::Missing))
+        }
+
+        async fn update_pr_branch(
+            &self,
+            key: &PullRequestKey,
+            method: UpdateMethod,
+            expected_head_sha: &str,
+        ) -> GithubResult<()> {
+            if self.failing_updates.lock().unwrap().contains(key) {
+                return Err(GithubError::UpdateBranchUnavailable);
+            }
+            if self.moved_heads.lock().unwrap().remove(key)
+                || expected_head_sha != self.head_sha(key)
+            {
+                return Err(GithubError::HeadMoved);
+            }
+
+            // Like GitHub does when someone else pushes to the branch.
+            self.auto_merges.lock().unwrap().remove(key);
+            self.updated_branches.lock().unwrap().push(key.clone());
+            self.update_methods
+                .lock()
+                .unwrap()
+                .insert(key.clone(), method);
+            {
+                let mut in_flight = self.updates_in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.updates_in_flight.lock().unwrap().0 -= 1;
+
+            Ok(())
+        }
+
+        async fn enable_auto_merge(
+            &self,
+            key: &PullRequestKey,
+            method: MergeMethod,
+            expected_head_sha: &str,
+        ) -> GithubResult<()> {
+            if expected_head_sha != self.head_sha(key) {
+                return Err(GithubError::HeadMoved);
+            }
+            self.auto_merges.lock().unwrap().insert(key.clone(), method);
+            Ok(())
+        }
+
+        async fn disable_auto_merge(&self, key: &PullRequestKey) -> GithubResult<()> {
+            self.auto_merges.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        async fn enqueue_pull_request(
+            &self,
+            key: &PullRequestKey,
+            expected_head_sha: &str,
+        ) -> GithubResult<()> {
+            if expected_head_sha != self.head_sha(key) {
+                return Err(GithubError::HeadMoved);
+            }
+            if self.failing_enqueues.lock().unwrap().contains(key) {
+                return Err(GithubError::Graphql(
+                    "Pull request is not mergeable".to_owned(),
+                ));
+            }
+            self.enqueued.lock().unwrap().push(key.clone());
+            Ok(())
+        }
+
+        async fn failed_jobs(
+            &self,
+            key: &PullRequestKey,
+            _head_sha: &str,
+        ) -> GithubResult<Vec<FailedJob>> {
+            if self.unreadable_ci.lock().unwrap().contains(key) {
+                return Err(GithubError::UnexpectedStatus(StatusCode::FORBIDDEN));
+            }
+            Ok(self
+                .failed_jobs
+                .lock()
+                .unwrap()
+                .get(key)
+                .cloned()
+                .unwrap_or_default())
+        }
+
+        async fn rerun_failed_jobs(&self, key: &PullRequestKey, run_id: u64) -> GithubResult<()> {
+            if self.failing_reruns.lock().unwrap().contains(&run_id) {
+                return Err(GithubError::UnexpectedStatus(StatusCode::FORBIDDEN));
+            }
+            self.reruns.lock().unwrap().push((key.clone(), run_id));
+            Ok(())
+        }
+
+        fn rate_limit(&self) -> Option<RateLimit> {
+            self.rate_limit.lock().unwrap().clone()
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers one connection per canned response, in order, and hands back
+    /// the request heads it received.
+    async fn serve(responses: Vec<String>) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_uri = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = Vec::new();
+            for response in responses {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+                while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                    let read = socket.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+                }
+                requests.push(String::from_utf8_lossy(&request).to_lowercase());
+                socket.write_all(response.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (base_uri, server)
+    }
 
     #[test]
     fn parse_github_pr_url_returns_repository_identity() {
@@ -154,4 +1524,477 @@ mod tests {
 
         assert_eq!(key, None);
     }
+
+    #[test]
+    fn snapshot_query_aliases_every_pull_request() {
+        let keys = [
+            PullRequestKey::new("acme", "widgets", 42),
+            PullRequestKey::new("acme", "gadgets", 7),
+        ];
+
+        let query = build_snapshot_query(&keys);
+
+        let text = query["query"].as_str().unwrap();
+        assert!(text.contains("pr0: repository(owner: $owner0, name: $repo0)"));
+        assert!(text.contains("pr1: repository(owner: $owner1, name: $repo1)"));
+        assert_eq!(query["variables"]["repo1"], "gadgets");
+        assert_eq!(query["variables"]["number0"], 42);
+    }
+
+    #[test]
+    fn snapshot_response_is_matched_to_keys_by_alias() {
+        let keys = [
+            PullRequestKey::new("acme", "widgets", 42),
+            PullRequestKey::new("acme", "widgets", 43),
+            PullRequestKey::new("acme", "gone", 1),
+            PullRequestKey::new("acme", "hidden", 2),
+        ];
+        let response = json!({
+            "data": {
+                "pr0": { "pullRequest": {
+                    "title": "Behind", "state": "OPEN", "merged": false, "mergeStateStatus": "BEHIND",
+                    "headRefOid": "abc123", "baseRefName": "main",
+                    "autoMergeRequest": { "mergeMethod": "SQUASH" },
+                    "isInMergeQueue": false, "isMergeQueueEnabled": false, "reviewDecision": "APPROVED"
+                } },
+                "pr1": { "pullRequest": {
+                    "title": "Merged", "state": "MERGED", "merged": true, "mergeStateStatus": "UNKNOWN",
+                    "headRefOid": "def456", "baseRefName": "main",
+                    "isInMergeQueue": false, "isMergeQueueEnabled": false, "reviewDecision": null
+                } },
+                "pr2": null,
+                "pr3": null
+            },
+            "errors": [
+                { "path": ["pr2"], "message": "Could not resolve to a Repository" }
+            ]
+        });
+
+        let snapshots = parse_snapshot_response(&keys, &response);
+
+        assert_eq!(
+            snapshots[0].as_ref().unwrap(),
+            &PullRequestSnapshot {
+                title: "Behind".to_owned(),
+                state: PullRequestState::Open,
+                status: PrStatus::Behind,
+                head_sha: "abc123".to_owned(),
+                base_ref: "main".to_owned(),
+                auto_merge_enabled: Some(true),
+                merge_queue: Some(false),
+                approved: Some(true),
+            }
+        );
+        assert_eq!(snapshots[1].as_ref().unwrap().status, PrStatus::Merged);
+        assert_eq!(
+            snapshots[1].as_ref().unwrap().state,
+            PullRequestState::Merged
+        );
+        assert!(matches!(
+            &snapshots[2],
+            Err(GithubError::Graphql(message)) if message == "Could not resolve to a Repository"
+        ));
+        assert!(matches!(
+            &snapshots[3],
+            Err(GithubError::Graphql(message)) if message == "pull request not found"
+        ));
+    }
+
+    #[test]
+    fn closed_unmerged_pull_request_is_told_apart_from_merged() {
+        let keys = [PullRequestKey::new("acme", "widgets", 42)];
+        let response = json!({
+            "data": {
+                "pr0": { "pullRequest": {
+                    "title": "Abandoned", "state": "CLOSED", "merged": false, "mergeStateStatus": "DIRTY",
+                    "headRefOid": "abc123", "baseRefName": "main",
+                    "isInMergeQueue": false, "isMergeQueueEnabled": true,
+                    "reviewDecision": "CHANGES_REQUESTED"
+                } }
+            }
+        });
+
+        let snapshots = parse_snapshot_response(&keys, &response);
+
+        assert_eq!(
+            snapshots[0].as_ref().unwrap(),
+            &PullRequestSnapshot {
+                title: "Abandoned".to_owned(),
+                state: PullRequestState::Closed,
+                status: PrStatus::Closed,
+                head_sha: "abc123".to_owned(),
+                base_ref: "main".to_owned(),
+                auto_merge_enabled: Some(false),
+                merge_queue: Some(true),
+                approved: Some(false),
+            }
+        );
+    }
+
+    #[test]
+    fn pull_request_in_the_merge_queue_is_queued_whatever_its_merge_state() {
+        let keys = [PullRequestKey::new("acme", "widgets", 42)];
+        let response = json!({
+            "data": {
+                "pr0": { "pullRequest": {
+                    "title": "Queued", "state": "OPEN", "merged": false, "mergeStateStatus": "BLOCKED",
+                    "headRefOid": "abc123", "baseRefName": "main",
+                    "isInMergeQueue": true, "isMergeQueueEnabled": true, "reviewDecision": "APPROVED"
+                } }
+            }
+        });
+
+        let snapshots = parse_snapshot_response(&keys, &response);
+
+        assert_eq!(snapshots[0].as_ref().unwrap().status, PrStatus::Queued);
+    }
+
+    fn snapshot(status: PrStatus) -> GithubResult<PullRequestSnapshot> {
+        Ok(PullRequestSnapshot {
+            title: "Title".to_owned(),
+            state: PullRequestState::Open,
+            status,
+            head_sha: "abc123".to_owned(),
+            base_ref: "main".to_owned(),
+            auto_merge_enabled: None,
+            merge_queue: None,
+            approved: None,
+        })
+    }
+
+    #[test]
+    fn unknown_merge_state_means_github_is_still_computing() {
+        assert_eq!(status_from_merge_state(false, "UNKNOWN"), PrStatus::Pending);
+        assert_eq!(status_from_merge_state(false, "DRAFT"), PrStatus::Unknown);
+        assert_eq!(status_from_merge_state(true, "UNKNOWN"), PrStatus::Merged);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_merge_states_are_polled_again_until_known() {
+        let keys = [
+            PullRequestKey::new("acme", "widgets", 1),
+            PullRequestKey::new("acme", "widgets", 2),
+        ];
+        let rounds = Mutex::new(Vec::new());
+
+        let snapshots = resolve_pending(
+            &keys,
+            vec![snapshot(PrStatus::Pending), snapshot(PrStatus::UpToDate)],
+            |keys| {
+                rounds.lock().unwrap().push(keys.clone());
+                let round = rounds.lock().unwrap().len();
+                async move {
+                    let status = if round < 2 {
+                        PrStatus::Pending
+                    } else {
+                        PrStatus::Behind
+                    };
+                    keys.iter().map(|_| snapshot(status)).collect()
+                }
+            },
+        )
+        .await;
+
+        assert_eq!(*rounds.lock().unwrap(), vec![vec![keys[0].clone()]; 2]);
+        assert_eq!(snapshots[0].as_ref().unwrap().status, PrStatus::Behind);
+        assert_eq!(snapshots[1].as_ref().unwrap().status, PrStatus::UpToDate);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn pending_merge_state_is_kept_after_the_last_retry() {
+        let keys = [PullRequestKey::new("acme", "widgets", 1)];
+        let mut rounds = 0;
+
+        let snapshots = resolve_pending(&keys, vec![snapshot(PrStatus::Pending)], |keys| {
+            rounds += 1;
+            async move { keys.iter().map(|_| snapshot(PrStatus::Pending)).collect() }
+        })
+        .await;
+
+        assert_eq!(rounds, MERGE_STATE_RETRY_DELAYS.len());
+        assert_eq!(snapshots[0].as_ref().unwrap().status, PrStatus::Pending);
+    }
+
+    fn rate_limit_headers(remaining: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-ratelimit-resource", "core".parse().unwrap());
+        headers.insert("x-ratelimit-limit", "5000".parse().unwrap());
+        headers.insert("x-ratelimit-remaining", remaining.parse().unwrap());
+        headers.insert("x-ratelimit-reset", "1700000000".parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn rate_limit_is_read_from_response_headers() {
+        let rate_limit = RateLimit::from_headers(&rate_limit_headers("42")).unwrap();
+
+        assert_eq!(
+            rate_limit,
+            RateLimit {
+                resource: "core".to_owned(),
+                limit: 5000,
+                remaining: 42,
+                reset_at: DateTime::from_timestamp(1_700_000_000, 0).unwrap(),
+            }
+        );
+        assert_eq!(RateLimit::from_headers(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn throttled_responses_say_when_to_retry() {
+        let now = DateTime::from_timestamp(1_699_999_000, 0).unwrap();
+        let mut retry_after = HeaderMap::new();
+        retry_after.insert("retry-after", "30".parse().unwrap());
+
+        assert_eq!(
+            retry_at(StatusCode::FORBIDDEN, &rate_limit_headers("0"), now),
+            DateTime::from_timestamp(1_700_000_000, 0)
+        );
+        assert_eq!(
+            retry_at(StatusCode::TOO_MANY_REQUESTS, &retry_after, now),
+            Some(now + TimeDelta::seconds(30))
+        );
+        assert_eq!(
+            retry_at(StatusCode::TOO_MANY_REQUESTS, &HeaderMap::new(), now),
+            Some(now + TimeDelta::seconds(DEFAULT_RETRY_AFTER_SECS))
+        );
+        assert_eq!(
+            retry_at(StatusCode::FORBIDDEN, &rate_limit_headers("12"), now),
+            None
+        );
+        assert_eq!(
+            retry_at(StatusCode::OK, &rate_limit_headers("0"), now),
+            None
+        );
+    }
+
+    #[test]
+    fn graphql_rate_limit_errors_are_recognised() {
+        let limited =
+            json!({ "errors": [{ "type": "RATE_LIMITED", "message": "API rate limit exceeded" }] });
+        let not_found =
+            json!({ "errors": [{ "type": "NOT_FOUND", "message": "Could not resolve" }] });
+
+        assert!(is_rate_limited(&limited));
+        assert!(!is_rate_limited(&not_found));
+    }
+
+    #[test]
+    fn ci_state_prefers_failures_over_pending_over_passing() {
+        let status = |state: &str, total_count| CombinedStatus {
+            state: state.to_owned(),
+            total_count,
+        };
+        let run = |status: &str, conclusion: Option<&str>| CheckRun {
+            name: "build".to_owned(),
+            status: status.to_owned(),
+            conclusion: conclusion.map(str::to_owned),
+            details_url: None,
+            app: None,
+        };
+
+        assert_eq!(
+            combine_ci_state(&status("pending", 0), &[]),
+            CiState::Missing
+        );
+        assert_eq!(
+            combine_ci_state(
+                &status("pending", 0),
+                &[
+                    run("completed", Some("success")),
+                    run("completed", Some("skipped"))
+                ]
+            ),
+            CiState::Passing
+        );
+        assert_eq!(
+            combine_ci_state(
+                &status("success", 1),
+                &[run("in_progress", None), run("completed", Some("success"))]
+            ),
+            CiState::Pending
+        );
+        assert_eq!(
+            combine_ci_state(
+                &status("pending", 2),
+                &[run("queued", None), run("completed", Some("failure"))]
+            ),
+            CiState::Failing
+        );
+        assert_eq!(combine_ci_state(&status("error", 1), &[]), CiState::Failing);
+    }
+
+    #[test]
+    fn only_failed_actions_jobs_are_offered_for_a_rerun() {
+        let checks: CheckRuns = serde_json::from_value(json!({
+            "check_runs": [
+                {
+                    "name": "test (ubuntu)", "status": "completed", "conclusion": "failure",
+                    "details_url": "https://github.com/acme/widgets/actions/runs/123/job/456",
+                    "app": { "slug": "github-actions" }
+                },
+                {
+                    "name": "lint", "status": "completed", "conclusion": "success",
+                    "details_url": "https://github.com/acme/widgets/actions/runs/123/job/457",
+                    "app": { "slug": "github-actions" }
+                },
+                {
+                    "name": "coverage", "status": "completed", "conclusion": "failure",
+                    "details_url": "https://coverage.example.com/acme/widgets",
+                    "app": { "slug": "coverage-bot" }
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            failed_actions_jobs(checks.check_runs),
+            vec![FailedJob {
+                name: "test (ubuntu)".to_owned(),
+                run_id: 123,
+            }]
+        );
+    }
+
+    fn local_provider(base_uri: &str) -> OctocrabProvider {
+        OctocrabProvider {
+            client: Octocrab::builder()
+                .base_uri(base_uri)
+                .unwrap()
+                .build()
+                .unwrap(),
+            rate_limits: Mutex::default(),
+            etag_cache: None,
+        }
+    }
+
+    fn json_response(status: &str, body: &Value) -> String {
+        let body = body.to_string();
+        format!(
+            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    #[tokio::test]
+    async fn only_a_stale_head_counts_as_a_moved_branch() {
+        let (base_uri, _server) = serve(vec![
+            json_response(
+                "422 Unprocessable Entity",
+                &json!({ "message": "expected head sha didn't match current head ref." }),
+            ),
+            json_response(
+                "422 Unprocessable Entity",
+                &json!({ "message": "merge conflict between base and head" }),
+            ),
+            json_response(
+                "200 OK",
+                &json!({ "errors": [{ "type": "STALE_DATA", "message": "Head moved" }] }),
+            ),
+            json_response(
+                "200 OK",
+                &json!({ "errors": [{ "type": "UNPROCESSABLE", "message": "No new commits" }] }),
+            ),
+        ])
+        .await;
+        let provider = local_provider(&base_uri);
+        let key = PullRequestKey::new("acme", "widgets", 42);
+
+        let moved = provider.merge_base_branch(&key, "abc123").await;
+        let conflicted = provider
+            .update_pr_branch(&key, UpdateMethod::Merge, "abc123")
+            .await;
+        let stale = provider.graphql(&json!({ "query": "mutation" })).await;
+        let refused = provider.graphql(&json!({ "query": "mutation" })).await;
+
+        assert!(matches!(moved, Err(GithubError::HeadMoved)));
+        assert!(matches!(
+            conflicted,
+            Err(GithubError::UpdateBranchUnavailable)
+        ));
+        assert!(matches!(stale, Err(GithubError::HeadMoved)));
+        assert!(
+            matches!(refused, Err(GithubError::Graphql(message)) if message == "No new commits")
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_pull_request_is_answered_from_the_etag_cache() {
+        let body = json!({
+            "url": "https://api.github.com/repos/acme/widgets/pulls/42",
+            "id": 1,
+            "number": 42,
+            "title": "Widgets",
+            "state": "open",
+            "mergeable_state": "behind",
+            "head": { "ref": "feature", "sha": "abc123" },
+            "base": { "ref": "main", "sha": "def456" }
+        })
+        .to_string();
+        let (base_uri, server) = serve(vec![
+            format!(
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            ),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n".to_owned(),
+        ])
+        .await;
+        let path = std::env::temp_dir().join(format!(
+            "pr-monitor-github-etag-{}.db",
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        let provider = local_provider(&base_uri).with_etag_cache(Storage::new(path).unwrap());
+        let key = PullRequestKey::new("acme", "widgets", 42);
+
+        let fresh = provider.rest_snapshot(&key).await.unwrap();
+        let replayed = provider.rest_snapshot(&key).await.unwrap();
+
+        assert_eq!(fresh.status, PrStatus::Behind);
+        assert_eq!(replayed, fresh);
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+    }
+
+    #[tokio::test]
+    async fn requests_fail_fast_while_the_budget_is_spent() {
+        let provider = OctocrabProvider::new("token").unwrap();
+        let reset_at = Utc::now() + TimeDelta::minutes(10);
+        provider.throttle("core", reset_at);
+
+        let result = provider
+            .rest_snapshot(&PullRequestKey::new("acme", "widgets", 42))
+            .await;
+
+        assert!(matches!(result, Err(GithubError::RateLimited { reset_at: at }) if at == reset_at));
+        assert_eq!(
+            provider.rate_limit().map(|rate_limit| rate_limit.remaining),
+            Some(0)
+        );
+    }
+
+    #[tokio::test]
+    async fn github_clients_reuse_the_client_for_the_same_token() {
+        let clients = GithubClients::default();
+
+        let first = clients.provider("token-a").unwrap();
+        let second = clients.provider("token-a").unwrap();
+
+        assert!(Arc::ptr_eq(&first, &second));
+    }
+
+    #[tokio::test]
+    async fn github_clients_rebuild_the_client_when_the_token_changes() {
+        let clients = GithubClients::default();
+
+        let first = clients.provider("token-a").unwrap();
+        let changed = clients.provider("token-b").unwrap();
+        clients.invalidate();
+        let rebuilt = clients.provider("token-b").unwrap();
+
+        assert!(!Arc::ptr_eq(&first, &changed));
+        assert!(!Arc::ptr_eq(&changed, &rebuilt));
+    }
 }
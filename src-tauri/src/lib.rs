@@ -1,4 +1,8 @@
+#[cfg(feature = "desktop")]
 pub mod app;
+pub mod audit;
+pub mod cli;
+#[cfg(feature = "desktop")]
 mod commands;
 mod credentials;
 mod diagnostics;
@@ -8,6 +12,7 @@ pub mod github;
 pub mod monitor;
 pub mod storage;
 
+#[cfg(feature = "desktop")]
 pub fn run() {
     app::run()
 }
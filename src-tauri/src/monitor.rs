@@ -1,17 +1,74 @@
-use crate::domain::PrStatus;
-use crate::error::{AppError, AppResult};
-use crate::github::{needs_update_pr, update_pr_branch};
+use crate::domain::{
+    AuditAction, AuditEntry, AuditResult, CiPolicy, CiState, MergeMethod, MonitorMode, PrStatus,
+    PullRequestEvent, PullRequestEventKind, PullRequestKey, PullRequestModel, PullRequestSnapshot,
+    PullRequestState, UpdateMethod,
+};
+use crate::error::{AppResult, GithubError};
+use crate::github::{FailedJob, PullRequestProvider, RateLimit};
 use crate::storage::Storage;
-use log::{error, info};
-use std::sync::Arc;
-use tauri::{Emitter, Wry};
-use tauri_plugin_notification::NotificationExt;
+use chrono::{DateTime, Utc};
+use futures::stream::{self, StreamExt};
+use log::{error, info, warn};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, PoisonError};
 use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
 
+/// Receives the side effects of a monitor check, so the loop can run with or
+/// without a window.
+///
+/// `update_failed`, `needs_attention`, `would_update_branch` and
+/// `reruns_exhausted` are only reported while notifications are enabled and
+/// never for PRs in silent mode. `needs_attention`, `would_update_branch` and
+/// `update_failed` fire when a PR moves into the status, and again only once
+/// the configured reminder interval has passed.
+pub trait MonitorSink: Send + Sync {
+    /// Reported after every successful check, whether or not the status changed.
+    fn status_checked(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()>;
+
+    /// GitHub could not tell us about this PR. The rest of the list is still checked.
+    fn check_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()>;
+
+    fn pr_merged(&self, key: &PullRequestKey) -> AppResult<()>;
+
+    /// The PR was closed without being merged. It keeps being watched for a while in case it
+    /// is reopened.
+    fn pr_closed(&self, key: &PullRequestKey) -> AppResult<()>;
+
+    fn pr_reopened(&self, key: &PullRequestKey) -> AppResult<()>;
+
+    fn branch_updated(&self, key: &PullRequestKey) -> AppResult<()>;
+
+    /// Dry run is on, so the branch of this behind PR was left as it is.
+    fn would_update_branch(&self, key: &PullRequestKey) -> AppResult<()>;
+
+    fn update_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()>;
+
+    fn needs_attention(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()>;
+
+    /// `jobs` still fail after the PR used up its re-runs. Reported once per run-out.
+    fn reruns_exhausted(&self, key: &PullRequestKey, jobs: &str) -> AppResult<()>;
+}
+
+/// Requests left untouched when pausing, so adding a PR from the window still works.
+const RATE_LIMIT_RESERVE: u64 = 10;
+
+/// How long a PR closed without merging is still checked in case it is reopened.
+const REOPEN_WINDOW_DAYS: i64 = 30;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RateLimitStatus {
+    #[serde(flatten)]
+    pub rate_limit: RateLimit,
+    pub paused_until: Option<DateTime<Utc>>,
+}
+
 #[derive(Clone, Default)]
 pub struct Monitor {
     handle: Arc<Mutex<Option<MonitorHandle>>>,
+    rate_limit: Arc<std::sync::Mutex<Option<RateLimitStatus>>>,
 }
 
 struct MonitorHandle {
@@ -23,9 +80,9 @@ impl Monitor {
     pub async fn start(
         &self,
         storage: Storage,
-        token: String,
+        provider: Arc<dyn PullRequestProvider>,
         refresh_time_secs: u64,
-        app_handle: tauri::AppHandle<Wry>,
+        sink: Arc<dyn MonitorSink>,
     ) {
         let mut monitor = self.handle.lock().await;
         if let Some(handle) = monitor.as_ref() {
@@ -41,6 +98,7 @@ impl Monitor {
 
         info!("Starting monitor PRs");
         let (stop_tx, mut stop_rx) = watch::channel(false);
+        let rate_limit = Arc::clone(&self.rate_limit);
 
         let task = tokio::spawn(async move {
             let refresh_duration = std::time::Duration::from_secs(refresh_time_secs);
@@ -61,7 +119,7 @@ impl Monitor {
                         );
 
                         let check_pull_requests =
-                            check_pull_requests(&storage, &token, &app_handle);
+                            check_pull_requests(&storage, provider.as_ref(), sink.as_ref());
 
                         tokio::select! {
                             changed = stop_rx.changed() => {
@@ -76,6 +134,38 @@ impl Monitor {
                                 }
                             }
                         }
+
+                        let status = provider.rate_limit().map(|budget| RateLimitStatus {
+                            paused_until: paused_until(&budget, Utc::now()),
+                            rate_limit: budget,
+                        });
+                        let resume_at = status.as_ref().and_then(|status| status.paused_until);
+                        *rate_limit.lock().unwrap_or_else(PoisonError::into_inner) = status;
+
+                        if let Some(resume_at) = resume_at {
+                            warn!("GitHub rate limit is nearly spent, pausing checks until {resume_at}");
+                            let wait = (resume_at - Utc::now()).to_std().unwrap_or_default();
+
+                            tokio::select! {
+                                changed = stop_rx.changed() => {
+                                    if changed.is_err() || *stop_rx.borrow() {
+                                        info!("Task stopped while rate limited!");
+                                        break 'monitor;
+                                    }
+                                }
+                                _ = tokio::time::sleep(wait) => {
+                                    info!("GitHub rate limit was reset, resuming checks");
+                                    if let Some(status) = rate_limit
+                                        .lock()
+                                        .unwrap_or_else(PoisonError::into_inner)
+                                        .as_mut()
+                                    {
+                                        status.paused_until = None;
+                                    }
+                                    interval.reset_immediately();
+                                }
+                            }
+                        }
                     }
                 }
             }
@@ -84,6 +174,14 @@ impl Monitor {
         *monitor = Some(MonitorHandle { stop_tx, task });
     }
 
+    /// The last budget GitHub reported to the running loop, and whether checks are paused.
+    pub fn rate_limit(&self) -> Option<RateLimitStatus> {
+        self.rate_limit
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
     pub async fn stop(&self) {
         let handle = {
             let mut monitor = self.handle.lock().await;
@@ -99,64 +197,2382 @@ impl Monitor {
     }
 }
 
-async fn check_pull_requests(
+/// Checks stop while the budget is down to the reserve, until GitHub resets it.
+fn paused_until(rate_limit: &RateLimit, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    (rate_limit.remaining <= RATE_LIMIT_RESERVE && rate_limit.reset_at > now)
+        .then_some(rate_limit.reset_at)
+}
+
+pub async fn check_pull_requests(
     storage: &Storage,
-    token: &str,
-    app_handle: &tauri::AppHandle<Wry>,
+    provider: &dyn PullRequestProvider,
+    sink: &dyn MonitorSink,
 ) -> AppResult<()> {
-    let show_notification = storage.get_show_notification().await?;
-    let pull_requests = storage.get_open_pull_requests().await?;
-
-    for pr in pull_requests {
-        let key = pr.key();
-        let pr_status = needs_update_pr(&key, token).await?;
-
-        match pr_status {
-            PrStatus::Merged => {
-                info!("PR was merged, updating status");
-                storage.mark_pull_request_closed(key.clone()).await?;
-                app_handle
-                    .emit("pr-closed", &key)
-                    .map_err(|err| AppError::Event(err.to_string()))?;
-            }
-            PrStatus::Behind => {
-                info!("PR is behind, updating branch");
-                if let Err(err) = update_pr_branch(&key, token).await {
-                    error!("Failed to update PR branch: {err}");
-                    if show_notification {
-                        app_handle
-                            .notification()
-                            .builder()
-                            .title("Failed to update PR")
-                            .body(err.to_string())
-                            .show()
-                            .map_err(|err| AppError::Notification(err.to_string()))?;
-                    }
+    let settings = CheckSettings {
+        notify: storage.get_show_notification().await?,
+        remind_after: storage
+            .get_reminder_hours()
+            .await?
+            .and_then(|hours| i64::try_from(hours).ok())
+            .and_then(chrono::Duration::try_hours),
+        dry_run: storage.get_dry_run().await?,
+        auto_enqueue: storage.get_auto_enqueue().await?,
+        rerun_budget: storage.get_rerun_budget().await?,
+        rerun_patterns: storage.get_rerun_job_patterns().await?,
+        ci_policy: storage.get_ci_policy().await?,
+        behind_threshold: storage.get_behind_threshold().await?,
+        budget: UpdateBudget {
+            per_hour: storage.get_max_updates_per_hour().await?,
+            spacing: storage
+                .get_update_spacing_minutes()
+                .await?
+                .and_then(|minutes| i64::try_from(minutes).ok())
+                .and_then(chrono::Duration::try_minutes),
+        },
+    };
+    let max_concurrent = storage.get_max_concurrent_checks().await?;
+    let repository_methods = storage.get_repository_update_methods().await?;
+    let repository_thresholds = storage.get_repository_behind_thresholds().await?;
+    let pull_requests = storage
+        .get_unmerged_pull_requests(Utc::now() - chrono::Duration::days(REOPEN_WINDOW_DAYS))
+        .await?;
+
+    let keys: Vec<_> = pull_requests.iter().map(PullRequestModel::key).collect();
+    let snapshots = provider.fetch_snapshots(&keys, max_concurrent).await;
+    let behind_by = count_commits_behind(provider, &keys, &snapshots, max_concurrent).await;
+    let rerun_counts = match settings.rerun_budget {
+        Some(_) if !settings.rerun_patterns.is_empty() => storage.get_rerun_counts().await?,
+        _ => HashMap::new(),
+    };
+    let enqueued_heads = if settings.auto_enqueue {
+        storage.get_enqueued_heads().await?
+    } else {
+        HashSet::new()
+    };
+
+    let mut update_methods: Vec<_> = pull_requests
+        .iter()
+        .zip(&keys)
+        .zip(&behind_by)
+        .map(|((pr, key), behind_by)| {
+            let repository = (key.owner.clone(), key.repo.clone());
+            let threshold = repository_thresholds
+                .get(&repository)
+                .copied()
+                .unwrap_or(settings.behind_threshold);
+            let behind_by = (*behind_by).filter(|commits| *commits > 0)?;
+            if behind_by < threshold {
+                info!(
+                    "{}/{}#{} is {behind_by} commit(s) behind, waiting for {threshold} before updating",
+                    key.owner, key.repo, key.pr_number
+                );
+                return None;
+            }
+
+            (pr.mode == MonitorMode::AutoUpdate).then(|| {
+                pr.update_method
+                    .or_else(|| repository_methods.get(&repository).copied())
+                    .unwrap_or_default()
+            })
+        })
+        .collect();
+    let held_back_by_ci = hold_back_for_ci(
+        provider,
+        &keys,
+        &snapshots,
+        &mut update_methods,
+        settings.ci_policy,
+        max_concurrent,
+    )
+    .await;
+    if settings.budget.is_limited() {
+        let now = Utc::now();
+        let recent_updates = storage
+            .get_branch_updates_since(now - settings.budget.window())
+            .await?;
+        settings.budget.defer_over_budget(
+            &pull_requests,
+            &mut update_methods,
+            &recent_updates,
+            now,
+        );
+    }
+
+    // The GitHub calls overlap, but `buffered` hands the outcomes back in list
+    // order, so storage and the sink see the same sequence on every tick.
+    let checks: Vec<_> = keys
+        .iter()
+        .zip(snapshots)
+        .zip(behind_by)
+        .zip(update_methods)
+        .zip(held_back_by_ci)
+        .zip(&pull_requests)
+        .map(
+            |(((((key, snapshot), behind_by), update_method), held_back_by_ci), pr)| {
+                let acts = pr.mode == MonitorMode::AutoUpdate && !settings.dry_run;
+                let reruns_left = settings
+                    .rerun_budget
+                    .filter(|_| acts && !settings.rerun_patterns.is_empty())
+                    .map(|budget| {
+                        budget.saturating_sub(rerun_counts.get(key).copied().unwrap_or(0))
+                    });
+                // The queue is only asked once per commit, so a PR it refuses
+                // is tried again after its next push rather than every tick.
+                let enqueue = settings.auto_enqueue
+                    && acts
+                    && snapshot.as_ref().is_ok_and(|snapshot| {
+                        !enqueued_heads.contains(&(key.clone(), snapshot.head_sha.clone()))
+                    });
+                let plan = CheckPlan {
+                    update_method,
+                    held_back_by_ci,
+                    auto_merge: pr.auto_merge.filter(|_| !settings.dry_run),
+                    enqueue,
+                    reruns_left,
+                };
+                run_check(provider, key, snapshot, behind_by, plan, &settings)
+            },
+        )
+        .collect();
+    let mut outcomes = stream::iter(checks).buffered(max_concurrent);
+    let mut pull_requests = pull_requests.iter().zip(&keys);
+
+    while let Some(outcome) = outcomes.next().await {
+        let Some((pr, key)) = pull_requests.next() else {
+            break;
+        };
+
+        let outcome = match outcome {
+            Ok(outcome) => outcome,
+            Err(err) => {
+                error!(
+                    "Failed to check {}/{}#{}: {err}",
+                    key.owner, key.repo, key.pr_number
+                );
+                storage
+                    .set_last_error(key.clone(), Some(err.to_string()))
+                    .await?;
+                sink.check_failed(key, &err.to_string())?;
+                continue;
+            }
+        };
+
+        if pr.last_error.is_some() {
+            storage.set_last_error(key.clone(), None).await?;
+        }
+        handle_status(storage, sink, pr, key, outcome, &settings).await?;
+    }
+
+    Ok(())
+}
+
+/// Asks GitHub how far each behind PR is behind its base. Up-to-date PRs are
+/// 0 behind, and PRs in any other state are left unknown. A failed comparison
+/// leaves the PR unknown too, so its branch is not updated this time.
+async fn count_commits_behind(
+    provider: &dyn PullRequestProvider,
+    keys: &[PullRequestKey],
+    snapshots: &[Result<PullRequestSnapshot, GithubError>],
+    max_concurrent: usize,
+) -> Vec<Option<u32>> {
+    let comparisons: Vec<_> = keys
+        .iter()
+        .zip(snapshots.iter())
+        .map(|(key, snapshot)| async move {
+            match snapshot {
+                Ok(snapshot) if snapshot.status == PrStatus::Behind => Some(
+                    provider
+                        .behind_by(key, &snapshot.base_ref, &snapshot.head_sha)
+                        .await,
+                ),
+                _ => None,
+            }
+        })
+        .collect();
+    let comparisons: Vec<_> = stream::iter(comparisons)
+        .buffered(max_concurrent)
+        .collect()
+        .await;
+
+    keys.iter()
+        .zip(snapshots)
+        .zip(comparisons)
+        .map(|((key, snapshot), comparison)| match comparison {
+            Some(Ok(commits)) => Some(commits),
+            Some(Err(err)) => {
+                warn!(
+                    "Failed to count how far {}/{}#{} is behind: {err}",
+                    key.owner, key.repo, key.pr_number
+                );
+                None
+            }
+            None => snapshot
+                .as_ref()
+                .is_ok_and(|snapshot| snapshot.status == PrStatus::UpToDate)
+                .then_some(0),
+        })
+        .collect()
+}
+
+/// Clears the update of every PR whose CI the policy doesn't let through yet,
+/// before the update budget is handed out, and returns which PRs were held back.
+async fn hold_back_for_ci(
+    provider: &dyn PullRequestProvider,
+    keys: &[PullRequestKey],
+    snapshots: &[Result<PullRequestSnapshot, GithubError>],
+    update_methods: &mut [Option<UpdateMethod>],
+    policy: CiPolicy,
+    max_concurrent: usize,
+) -> Vec<bool> {
+    // CI is only asked about when a policy could hold the update back.
+    if policy == CiPolicy::Always {
+        return vec![false; keys.len()];
+    }
+
+    let lookups: Vec<_> = keys
+        .iter()
+        .zip(snapshots)
+        .zip(update_methods.iter())
+        .map(|((key, snapshot), update_method)| async move {
+            match (snapshot, update_method) {
+                (Ok(snapshot), Some(_)) => {
+                    !ci_allows(provider, key, &snapshot.head_sha, policy).await
+                }
+                _ => false,
+            }
+        })
+        .collect();
+    let held_back: Vec<bool> = stream::iter(lookups)
+        .buffered(max_concurrent)
+        .collect()
+        .await;
+
+    for (update_method, held_back) in update_methods.iter_mut().zip(&held_back) {
+        if *held_back {
+            *update_method = None;
+        }
+    }
+    held_back
+}
+
+/// Settings read once per tick.
+struct CheckSettings {
+    notify: bool,
+    remind_after: Option<chrono::Duration>,
+    /// Report what would be written to GitHub instead of writing it.
+    dry_run: bool,
+    /// Add approved PRs with passing CI to their merge queue.
+    auto_enqueue: bool,
+    /// Workflow runs each PR may re-run. New commits don't reset it, so the
+    /// monitor's own branch updates can't hand out more.
+    rerun_budget: Option<u32>,
+    /// Job names that may be re-run, where `*` matches anything.
+    rerun_patterns: Vec<String>,
+    ci_policy: CiPolicy,
+    /// Commits a PR must be behind before it is updated, unless its repository overrides it.
+    behind_threshold: u32,
+    budget: UpdateBudget,
+}
+
+/// Caps how fast one repository's branches are updated, so a busy base branch
+/// doesn't start every PR's CI at once.
+struct UpdateBudget {
+    per_hour: Option<u32>,
+    /// Least time between two updates in the same repository.
+    spacing: Option<chrono::Duration>,
+}
+
+impl UpdateBudget {
+    fn is_limited(&self) -> bool {
+        self.per_hour.is_some() || self.spacing.is_some()
+    }
+
+    /// How far back past updates count against the budget.
+    fn window(&self) -> chrono::Duration {
+        self.spacing
+            .unwrap_or_default()
+            .max(chrono::Duration::hours(1))
+    }
+
+    /// How many more updates a repository may get now, given when its recent
+    /// ones were made, oldest first. `None` means there is no limit.
+    fn allowance(&self, recent: &[DateTime<Utc>], now: DateTime<Utc>) -> Option<usize> {
+        let hourly = self.per_hour.map(|limit| {
+            let last_hour = recent
+                .iter()
+                .filter(|at| now - **at < chrono::Duration::hours(1))
+                .count();
+            (limit as usize).saturating_sub(last_hour)
+        });
+        let spaced = self.spacing.map(|spacing| match recent.last() {
+            Some(last) if now - *last < spacing => 0,
+            _ => 1,
+        });
+
+        match (hourly, spaced) {
+            (Some(hourly), Some(spaced)) => Some(hourly.min(spaced)),
+            (hourly, spaced) => hourly.or(spaced),
+        }
+    }
+
+    /// Clears the update of every PR past its repository's budget, leaving it
+    /// for a later tick. PRs that have been behind the longest go first.
+    fn defer_over_budget(
+        &self,
+        pull_requests: &[PullRequestModel],
+        update_methods: &mut [Option<UpdateMethod>],
+        recent_updates: &HashMap<(String, String), Vec<DateTime<Utc>>>,
+        now: DateTime<Utc>,
+    ) {
+        let mut queue: Vec<_> = (0..pull_requests.len())
+            .filter(|index| update_methods[*index].is_some())
+            .collect();
+        queue.sort_by_key(|index| {
+            let pr = &pull_requests[*index];
+            let behind_since = (pr.last_status == Some(PrStatus::Behind))
+                .then_some(pr.status_changed_at)
+                .flatten();
+            (behind_since.is_none(), behind_since)
+        });
+
+        let mut allowances = HashMap::new();
+        for index in queue {
+            let pr = &pull_requests[index];
+            let repository = (pr.owner.clone(), pr.repo.clone());
+            let allowance = allowances
+                .entry(repository)
+                .or_insert_with_key(|repository| {
+                    let recent = recent_updates.get(repository).map(Vec::as_slice);
+                    self.allowance(recent.unwrap_or_default(), now)
+                });
+            match allowance {
+                Some(0) => {
+                    info!(
+                        "{}/{}#{} is behind, deferring its update until the repository's update budget allows it",
+                        pr.owner, pr.repo, pr.pr_number
+                    );
+                    update_methods[index] = None;
+                }
+                Some(left) => *left -= 1,
+                None => {}
+            }
+        }
+    }
+}
+
+impl CheckSettings {
+    /// Attention is due when the status just changed from `previous`, or when
+    /// it has been waiting unanswered for longer than the reminder interval.
+    fn attention_due(
+        &self,
+        pr: &PullRequestModel,
+        previous: Option<PrStatus>,
+        status: PrStatus,
+        now: DateTime<Utc>,
+    ) -> bool {
+        if previous != Some(status) {
+            return true;
+        }
+
+        match (self.remind_after, pr.last_notified_at) {
+            (Some(remind_after), Some(notified_at)) => now - notified_at >= remind_after,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// What a check may change on GitHub for one PR, depending on what it finds.
+struct CheckPlan {
+    /// Update the branch with this method if it is behind.
+    update_method: Option<UpdateMethod>,
+    /// The PR is behind but the CI policy holds its update back.
+    held_back_by_ci: bool,
+    /// Keep auto-merge on with this method.
+    auto_merge: Option<MergeMethod>,
+    /// Add the PR to its merge queue once it is approved and CI passed.
+    enqueue: bool,
+    /// Workflow runs the PR may still re-run, or `None` when failed jobs are
+    /// not re-run.
+    reruns_left: Option<u32>,
+}
+
+/// What happened to the failed jobs allowed to be re-run.
+enum JobReruns {
+    Started {
+        jobs: Vec<String>,
+        /// One result for each workflow run that was re-run.
+        results: Vec<(u64, Result<(), GithubError>)>,
+    },
+    /// The jobs failed again but the PR has no re-runs left.
+    Exhausted { jobs: Vec<String> },
+}
+
+/// What the GitHub side of a check found out about one PR.
+struct CheckOutcome {
+    status: PrStatus,
+    head_sha: String,
+    behind_by: Option<u32>,
+    /// Dry run is on, and the PR's branch would have been updated otherwise.
+    would_update: bool,
+    /// Set when the PR was behind and its branch update was attempted, with the
+    /// method that was used.
+    branch_update: Option<(UpdateMethod, Result<(), GithubError>)>,
+    /// The PR was behind but the CI policy held its update back.
+    held_back_by_ci: bool,
+    auto_merge_enabled: Option<bool>,
+    /// Set when auto-merge was off and was turned back on, or the PR merged.
+    auto_merge: Option<(AuditAction, Result<(), GithubError>)>,
+    /// Set when the PR was added to its merge queue.
+    enqueued: Option<Result<(), GithubError>>,
+    reruns: Option<JobReruns>,
+}
+
+async fn run_check(
+    provider: &dyn PullRequestProvider,
+    key: &PullRequestKey,
+    snapshot: Result<PullRequestSnapshot, GithubError>,
+    behind_by: Option<u32>,
+    plan: CheckPlan,
+    settings: &CheckSettings,
+) -> Result<CheckOutcome, GithubError> {
+    let PullRequestSnapshot {
+        status,
+        head_sha,
+        auto_merge_enabled,
+        merge_queue,
+        approved,
+        ..
+    } = snapshot?;
+    // A queued PR is merged onto the latest base by the queue, so it is never
+    // `Behind` and its branch is left alone.
+    let update_method = plan.update_method.filter(|_| status == PrStatus::Behind);
+
+    let branch_update = match update_method {
+        Some(method) if settings.dry_run => {
+            info!("Dry run: would update the branch with {method}");
+            None
+        }
+        Some(method) => {
+            info!("PR is behind, updating branch with {method}");
+            let result = provider.update_pr_branch(key, method, &head_sha).await;
+            Some((method, result))
+        }
+        _ => None,
+    };
+
+    // Pushing to the branch turns auto-merge off, so it is turned back on once
+    // the next check sees the new head.
+    let auto_merge = match plan.auto_merge {
+        Some(method) if auto_merge_enabled == Some(false) && branch_update.is_none() => {
+            apply_auto_merge(provider, key, method, status, &head_sha).await
+        }
+        _ => None,
+    };
+
+    let mut enqueued = None;
+    if plan.enqueue
+        && merge_queue == Some(true)
+        && approved == Some(true)
+        && matches!(
+            status,
+            PrStatus::UpToDate | PrStatus::Behind | PrStatus::Blocked
+        )
+        && branch_update.is_none()
+        && auto_merge.is_none()
+    {
+        match provider.ci_state(key, &head_sha).await {
+            Ok(CiState::Passing) => {
+                info!("PR is approved and CI passed, adding it to the merge queue");
+                enqueued = Some(provider.enqueue_pull_request(key, &head_sha).await);
+            }
+            Ok(ci_state) => info!("CI is {ci_state}, not adding the PR to the merge queue yet"),
+            Err(err) => warn!("Failed to read CI, not adding the PR to the merge queue: {err}"),
+        }
+    }
+
+    // Failing checks keep a PR blocked, or unstable when they are not required.
+    let reruns = match plan.reruns_left {
+        Some(reruns_left)
+            if matches!(status, PrStatus::Blocked | PrStatus::Unknown)
+                && branch_update.is_none() =>
+        {
+            match provider.failed_jobs(key, &head_sha).await {
+                Ok(failed) => {
+                    let patterns = &settings.rerun_patterns;
+                    rerun_failed_jobs(provider, key, failed, patterns, reruns_left).await
+                }
+                Err(err) => {
+                    warn!("Failed to list the failed jobs, not re-running any: {err}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    };
+
+    Ok(CheckOutcome {
+        status,
+        head_sha,
+        behind_by,
+        would_update: update_method.is_some() && settings.dry_run,
+        branch_update,
+        held_back_by_ci: plan.held_back_by_ci,
+        auto_merge_enabled,
+        auto_merge,
+        enqueued,
+        reruns,
+    })
+}
+
+/// Whether CI on `head_sha` passes `policy`. CI that can't be read holds the
+/// change back, rather than failing the whole check.
+async fn ci_allows(
+    provider: &dyn PullRequestProvider,
+    key: &PullRequestKey,
+    head_sha: &str,
+    policy: CiPolicy,
+) -> bool {
+    match provider.ci_state(key, head_sha).await {
+        Ok(ci_state) if policy.allows(ci_state) => true,
+        Ok(ci_state) => {
+            info!("CI is {ci_state}, holding back ({policy} policy)");
+            false
+        }
+        Err(err) => {
+            warn!(
+                "Failed to read CI for {}/{}#{}, holding back: {err}",
+                key.owner, key.repo, key.pr_number
+            );
+            false
+        }
+    }
+}
+
+/// Re-runs the workflow runs of the failed jobs that match `patterns`, one
+/// run for each re-run the PR has left.
+async fn rerun_failed_jobs(
+    provider: &dyn PullRequestProvider,
+    key: &PullRequestKey,
+    failed: Vec<FailedJob>,
+    patterns: &[String],
+    reruns_left: u32,
+) -> Option<JobReruns> {
+    let failed: Vec<_> = failed
+        .into_iter()
+        .filter(|job| {
+            patterns
+                .iter()
+                .any(|pattern| job_matches(pattern, &job.name))
+        })
+        .collect();
+    if failed.is_empty() {
+        return None;
+    }
+    if reruns_left == 0 {
+        let jobs = failed.into_iter().map(|job| job.name).collect();
+        return Some(JobReruns::Exhausted { jobs });
+    }
+
+    let mut run_ids: Vec<_> = failed.iter().map(|job| job.run_id).collect();
+    run_ids.sort_unstable();
+    run_ids.dedup();
+    run_ids.truncate(usize::try_from(reruns_left).unwrap_or(usize::MAX));
+    let jobs: Vec<_> = failed
+        .into_iter()
+        .filter(|job| run_ids.contains(&job.run_id))
+        .map(|job| job.name)
+        .collect();
+    info!("Re-running failed jobs {jobs:?}, {reruns_left} re-run(s) left");
+    let mut results = Vec::with_capacity(run_ids.len());
+    for run_id in run_ids {
+        results.push((run_id, provider.rerun_failed_jobs(key, run_id).await));
+    }
+
+    Some(JobReruns::Started { jobs, results })
+}
+
+/// `*` matches any run of characters, everything else matches itself ignoring case.
+fn job_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<_> = pattern.split('*').map(regex::escape).collect();
+    Regex::new(&format!("(?i)^{}$", pattern.join(".*"))).is_ok_and(|regex| regex.is_match(name))
+}
+
+/// Turns auto-merge on for an open PR. GitHub won't turn it on for a PR that
+/// can already be merged, so that one is left for someone to merge, and PRs
+/// with conflicts are left alone until they are resolved.
+async fn apply_auto_merge(
+    provider: &dyn PullRequestProvider,
+    key: &PullRequestKey,
+    method: MergeMethod,
+    status: PrStatus,
+    head_sha: &str,
+) -> Option<(AuditAction, Result<(), GithubError>)> {
+    match status {
+        PrStatus::UpToDate => {
+            info!("PR can already be merged, leaving the merge to you");
+            None
+        }
+        PrStatus::Behind | PrStatus::Blocked | PrStatus::Pending | PrStatus::Unknown => {
+            info!("Enabling auto-merge with {method}");
+            let result = provider.enable_auto_merge(key, method, head_sha).await;
+            Some((AuditAction::EnableAutoMerge, result))
+        }
+        PrStatus::Conflicts | PrStatus::Queued | PrStatus::Merged | PrStatus::Closed => None,
+    }
+}
+
+/// Keeps auto-merge on for the PR with `method`, or stops doing so with `None`,
+/// and applies the change on GitHub straight away unless dry run is on.
+pub async fn set_auto_merge(
+    storage: &Storage,
+    provider: &dyn PullRequestProvider,
+    key: &PullRequestKey,
+    method: Option<MergeMethod>,
+) -> AppResult<()> {
+    storage
+        .set_pull_request_auto_merge(key.clone(), method)
+        .await?;
+    if storage.get_dry_run().await? {
+        info!("Dry run: leaving auto-merge on GitHub as it is");
+        return Ok(());
+    }
+
+    let snapshot = provider
+        .fetch_snapshots(std::slice::from_ref(key), 1)
+        .await
+        .pop()
+        .ok_or(GithubError::PullRequestDetailsUnavailable)??;
+    let action = match method {
+        Some(method) => {
+            apply_auto_merge(provider, key, method, snapshot.status, &snapshot.head_sha).await
+        }
+        None if snapshot.auto_merge_enabled != Some(false) => {
+            info!("Disabling auto-merge");
+            let result = provider.disable_auto_merge(key).await;
+            Some((AuditAction::DisableAutoMerge, result))
+        }
+        None => None,
+    };
+
+    if let Some((action, result)) = action {
+        let enabled = match (&action, &result) {
+            (AuditAction::EnableAutoMerge, Ok(())) => true,
+            (AuditAction::DisableAutoMerge, Ok(())) => false,
+            _ => snapshot.auto_merge_enabled.unwrap_or_default(),
+        };
+        storage.set_auto_merge_enabled(key.clone(), enabled).await?;
+        record_in_place(storage, key, snapshot.head_sha, action, &result, Utc::now()).await?;
+        result?;
+    }
+
+    Ok(())
+}
+
+/// Records a change that leaves the head where it was, like auto-merge or the
+/// merge queue, so its entry is complete right away.
+async fn record_in_place(
+    storage: &Storage,
+    key: &PullRequestKey,
+    head_sha: String,
+    action: AuditAction,
+    result: &Result<(), GithubError>,
+    at: DateTime<Utc>,
+) -> AppResult<()> {
+    let audit = AuditEntry {
+        key: key.clone(),
+        action,
+        head_sha_before: head_sha.clone(),
+        head_sha_after: Some(head_sha),
+        result: match result {
+            Ok(()) => AuditResult::Succeeded,
+            Err(_) => AuditResult::Failed,
+        },
+        error: result.as_ref().err().map(ToString::to_string),
+        at,
+    };
+    storage.record_audit_entry(audit).await?;
+
+    Ok(())
+}
+
+async fn handle_status(
+    storage: &Storage,
+    sink: &dyn MonitorSink,
+    pr: &PullRequestModel,
+    key: &PullRequestKey,
+    outcome: CheckOutcome,
+    settings: &CheckSettings,
+) -> AppResult<()> {
+    let pr_status = outcome.status;
+    let now = Utc::now();
+    let notify = settings.notify && pr.mode != MonitorMode::Silent;
+    // `Pending` only means GitHub was still computing, so a PR that goes from
+    // conflicts to pending and back has not changed.
+    let previous_status = match pr.last_status {
+        Some(PrStatus::Pending) => storage
+            .get_pull_request_events(key.clone())
+            .await?
+            .into_iter()
+            .rev()
+            .filter(|event| event.kind == PullRequestEventKind::StatusChanged)
+            .find_map(|event| event.status.filter(|status| *status != PrStatus::Pending)),
+        status => status,
+    };
+    let attention_due = settings.attention_due(pr, previous_status, pr_status, now);
+    storage.record_status(key.clone(), pr_status, now).await?;
+    if pr.behind_by != outcome.behind_by {
+        storage
+            .set_behind_by(key.clone(), outcome.behind_by)
+            .await?;
+    }
+    storage
+        .complete_audit_entries(key.clone(), outcome.head_sha.clone())
+        .await?;
+    if let Some((action, result)) = &outcome.auto_merge {
+        match result {
+            Ok(()) => info!("{} went through", action.as_str()),
+            Err(err) => warn!("{} failed: {err}", action.as_str()),
+        }
+        record_in_place(storage, key, outcome.head_sha.clone(), *action, result, now).await?;
+    }
+    match &outcome.reruns {
+        Some(JobReruns::Started { jobs, results }) => {
+            for (run_id, result) in results {
+                if let Err(err) = result {
+                    warn!("Failed to re-run workflow run {run_id}: {err}");
                 }
+                record_in_place(
+                    storage,
+                    key,
+                    outcome.head_sha.clone(),
+                    AuditAction::RerunFailedJobs,
+                    result,
+                    now,
+                )
+                .await?;
             }
-            PrStatus::Conflicts | PrStatus::Blocked | PrStatus::Unknown => {
-                if show_notification {
-                    let status_str = match pr_status {
-                        PrStatus::Conflicts => "has conflicts",
-                        PrStatus::Blocked => "is blocked",
-                        _ => "has an unknown status",
-                    };
-                    let title = format!("PR Not Updated: #{}", key.pr_number);
-                    let body = format!("PR {} - please check.", status_str);
-                    app_handle
-                        .notification()
-                        .builder()
-                        .title(&title)
-                        .body(&body)
-                        .show()
-                        .map_err(|err| AppError::Notification(err.to_string()))?;
+            if results.iter().any(|(_, result)| result.is_ok()) {
+                let event = PullRequestEvent {
+                    detail: Some(jobs.join(", ")),
+                    ..PullRequestEvent::new(PullRequestEventKind::JobsRerun, now)
+                };
+                storage.record_event(key.clone(), event).await?;
+            }
+        }
+        Some(JobReruns::Exhausted { jobs }) => {
+            // The run-out is reported once, until a later re-run resets it.
+            let events = storage.get_pull_request_events(key.clone()).await?;
+            let reported = events
+                .iter()
+                .rev()
+                .find(|event| {
+                    matches!(
+                        event.kind,
+                        PullRequestEventKind::JobsRerun | PullRequestEventKind::RerunsExhausted
+                    )
+                })
+                .is_some_and(|event| event.kind == PullRequestEventKind::RerunsExhausted);
+            if !reported {
+                let jobs = jobs.join(", ");
+                info!("{jobs} still failing with no re-runs left");
+                let event = PullRequestEvent {
+                    detail: Some(jobs.clone()),
+                    ..PullRequestEvent::new(PullRequestEventKind::RerunsExhausted, now)
+                };
+                storage.record_event(key.clone(), event).await?;
+                if notify {
+                    sink.reruns_exhausted(key, &jobs)?;
+                }
+            }
+        }
+        None => {}
+    }
+    if let Some(result) = &outcome.enqueued {
+        if let Err(err) = result {
+            warn!("Failed to add the PR to the merge queue: {err}");
+        }
+        let head_sha = outcome.head_sha.clone();
+        record_in_place(storage, key, head_sha, AuditAction::Enqueue, result, now).await?;
+    }
+    let auto_merge_enabled = match outcome.auto_merge {
+        Some((AuditAction::EnableAutoMerge, Ok(()))) => Some(true),
+        _ => outcome.auto_merge_enabled,
+    };
+    if let Some(enabled) = auto_merge_enabled.filter(|enabled| *enabled != pr.auto_merge_enabled) {
+        storage.set_auto_merge_enabled(key.clone(), enabled).await?;
+    }
+    if pr.last_status != Some(pr_status) {
+        let event = PullRequestEvent {
+            status: Some(pr_status),
+            ..PullRequestEvent::new(PullRequestEventKind::StatusChanged, now)
+        };
+        storage.record_event(key.clone(), event).await?;
+    }
+    sink.status_checked(key, pr_status)?;
+
+    let state = match pr_status {
+        PrStatus::Merged => PullRequestState::Merged,
+        PrStatus::Closed => PullRequestState::Closed,
+        _ => PullRequestState::Open,
+    };
+    if state != pr.state {
+        info!("PR went from {} to {state}, updating status", pr.state);
+        storage
+            .set_pull_request_state(key.clone(), state, now)
+            .await?;
+        let kind = match state {
+            PullRequestState::Merged => PullRequestEventKind::Merged,
+            PullRequestState::Closed => PullRequestEventKind::Closed,
+            PullRequestState::Open => PullRequestEventKind::Reopened,
+        };
+        storage
+            .record_event(key.clone(), PullRequestEvent::new(kind, now))
+            .await?;
+        match state {
+            PullRequestState::Merged => sink.pr_merged(key)?,
+            PullRequestState::Closed => sink.pr_closed(key)?,
+            PullRequestState::Open => sink.pr_reopened(key)?,
+        }
+    }
+
+    match pr_status {
+        PrStatus::Merged | PrStatus::Closed => {}
+        PrStatus::Behind if pr.mode != MonitorMode::AutoUpdate => {
+            info!("PR is behind, leaving the branch alone in {} mode", pr.mode);
+            if notify && attention_due {
+                sink.needs_attention(key, pr_status)?;
+                storage.set_last_notified_at(key.clone(), now).await?;
+            }
+        }
+        PrStatus::Behind if outcome.would_update => {
+            if notify && attention_due {
+                sink.would_update_branch(key)?;
+                storage.set_last_notified_at(key.clone(), now).await?;
+            }
+        }
+        PrStatus::Behind => {
+            if outcome.held_back_by_ci {
+                info!("PR is behind, but CI held the update back");
+            }
+            if let Some((method, result)) = outcome.branch_update {
+                let audit = AuditEntry {
+                    key: key.clone(),
+                    action: match method {
+                        UpdateMethod::Merge => AuditAction::UpdateBranch,
+                        UpdateMethod::Rebase => AuditAction::RebaseBranch,
+                    },
+                    head_sha_before: outcome.head_sha,
+                    head_sha_after: None,
+                    result: match result {
+                        Ok(()) => AuditResult::Succeeded,
+                        Err(_) => AuditResult::Failed,
+                    },
+                    error: result.as_ref().err().map(ToString::to_string),
+                    at: now,
+                };
+                storage.record_audit_entry(audit).await?;
+
+                match result {
+                    Ok(()) => {
+                        let event = PullRequestEvent::new(PullRequestEventKind::BranchUpdated, now);
+                        storage.record_event(key.clone(), event).await?;
+                        sink.branch_updated(key)?;
+                    }
+                    Err(err @ GithubError::HeadMoved) => {
+                        warn!("PR branch moved while it was checked: {err}");
+                        let event = PullRequestEvent {
+                            detail: Some(err.to_string()),
+                            ..PullRequestEvent::new(PullRequestEventKind::BranchUpdateFailed, now)
+                        };
+                        storage.record_event(key.clone(), event).await?;
+                    }
+                    Err(err) => {
+                        error!("Failed to update PR branch: {err}");
+                        let event = PullRequestEvent {
+                            detail: Some(err.to_string()),
+                            ..PullRequestEvent::new(PullRequestEventKind::BranchUpdateFailed, now)
+                        };
+                        storage.record_event(key.clone(), event).await?;
+                        if notify && attention_due {
+                            sink.update_failed(key, &err.to_string())?;
+                            storage.set_last_notified_at(key.clone(), now).await?;
+                        }
+                    }
                 }
             }
-            PrStatus::UpToDate => {
-                info!("PR is up to date");
+        }
+        PrStatus::Conflicts | PrStatus::Blocked | PrStatus::Unknown => {
+            if notify && attention_due {
+                sink.needs_attention(key, pr_status)?;
+                storage.set_last_notified_at(key.clone(), now).await?;
             }
         }
+        PrStatus::UpToDate => {
+            info!("PR is up to date");
+        }
+        PrStatus::Queued => {
+            info!("PR is in the merge queue, leaving the branch alone");
+        }
+        PrStatus::Pending => {
+            info!("GitHub is still computing the merge state, checking again next time");
+        }
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+pub(crate) mod fake {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub enum MonitorEvent {
+        StatusChecked(PullRequestKey, PrStatus),
+        CheckFailed(PullRequestKey, String),
+        PrMerged(PullRequestKey),
+        PrClosed(PullRequestKey),
+        PrReopened(PullRequestKey),
+        BranchUpdated(PullRequestKey),
+        WouldUpdateBranch(PullRequestKey),
+        UpdateFailed(PullRequestKey, String),
+        NeedsAttention(PullRequestKey, PrStatus),
+        RerunsExhausted(PullRequestKey, String),
+    }
+
+    /// Keeps every event in memory so tests can assert on what a check produced.
+    #[derive(Default)]
+    pub struct RecordingSink {
+        events: Mutex<Vec<MonitorEvent>>,
+    }
+
+    impl RecordingSink {
+        pub fn events(&self) -> Vec<MonitorEvent> {
+            self.events.lock().expect("events lock").clone()
+        }
+
+        fn record(&self, event: MonitorEvent) -> AppResult<()> {
+            self.events.lock().expect("events lock").push(event);
+            Ok(())
+        }
+    }
+
+    impl MonitorSink for RecordingSink {
+        fn status_checked(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()> {
+            self.record(MonitorEvent::StatusChecked(key.clone(), status))
+        }
+
+        fn check_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()> {
+            self.record(MonitorEvent::CheckFailed(key.clone(), error.to_owned()))
+        }
+
+        fn pr_merged(&self, key: &PullRequestKey) -> AppResult<()> {
+            self.record(MonitorEvent::PrMerged(key.clone()))
+        }
+
+        fn pr_closed(&self, key: &PullRequestKey) -> AppResult<()> {
+            self.record(MonitorEvent::PrClosed(key.clone()))
+        }
+
+        fn pr_reopened(&self, key: &PullRequestKey) -> AppResult<()> {
+            self.record(MonitorEvent::PrReopened(key.clone()))
+        }
+
+        fn branch_updated(&self, key: &PullRequestKey) -> AppResult<()> {
+            self.record(MonitorEvent::BranchUpdated(key.clone()))
+        }
+
+        fn would_update_branch(&self, key: &PullRequestKey) -> AppResult<()> {
+            self.record(MonitorEvent::WouldUpdateBranch(key.clone()))
+        }
+
+        fn update_failed(&self, key: &PullRequestKey, error: &str) -> AppResult<()> {
+            self.record(MonitorEvent::UpdateFailed(key.clone(), error.to_owned()))
+        }
+
+        fn needs_attention(&self, key: &PullRequestKey, status: PrStatus) -> AppResult<()> {
+            self.record(MonitorEvent::NeedsAttention(key.clone(), status))
+        }
+
+        fn reruns_exhausted(&self, key: &PullRequestKey, jobs: &str) -> AppResult<()> {
+            self.record(MonitorEvent::RerunsExhausted(key.clone(), jobs.to_owned()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fake::{MonitorEvent, RecordingSink};
+    use super::*;
+    use crate::error::{AppError, StorageError};
+    use crate::github::fake::ScriptedProvider;
+    use chrono::TimeDelta;
+    use std::path::PathBuf;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_db_path(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time should be after unix epoch")
+            .as_nanos();
+        std::env::temp_dir().join(format!("pr-monitor-monitor-{name}-{nanos}.db"))
+    }
+
+    fn pull_request(key: &PullRequestKey) -> PullRequestModel {
+        PullRequestModel {
+            owner: key.owner.clone(),
+            repo: key.repo.clone(),
+            pr_number: key.pr_number,
+            title: format!("{}/{}#{}", key.owner, key.repo, key.pr_number),
+            state: PullRequestState::Open,
+            closed_at: None,
+            url: format!(
+                "https://api.github.com/repos/{}/{}/pulls/{}",
+                key.owner, key.repo, key.pr_number
+            ),
+            last_error: None,
+            last_status: None,
+            last_checked_at: None,
+            added_at: None,
+            merged_at: None,
+            status_changed_at: None,
+            last_notified_at: None,
+            mode: MonitorMode::default(),
+            update_method: None,
+            behind_by: None,
+            auto_merge: None,
+            auto_merge_enabled: false,
+        }
+    }
+
+    async fn storage_with(name: &str, keys: &[&PullRequestKey]) -> Storage {
+        let storage = Storage::new(temp_db_path(name)).expect("storage should initialize");
+        for key in keys {
+            storage.add_pull_request(pull_request(key)).await.unwrap();
+        }
+        storage
+    }
+
+    #[tokio::test]
+    async fn behind_pull_request_is_updated_until_merged() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("lifecycle", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(
+            &key,
+            [PrStatus::Behind, PrStatus::UpToDate, PrStatus::Merged],
+        );
+        let sink = RecordingSink::default();
+
+        for _ in 0..3 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(provider.updated_branches(), vec![key.clone()]);
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                MonitorEvent::BranchUpdated(key.clone()),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::UpToDate),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Merged),
+                MonitorEvent::PrMerged(key.clone()),
+            ]
+        );
+        assert!(storage
+            .get_unmerged_pull_requests(DateTime::<Utc>::MIN_UTC)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn ci_policy_holds_back_updates_until_ci_allows_them() {
+        let passing = PullRequestKey::new("acme", "widgets", 1);
+        let pending = PullRequestKey::new("acme", "widgets", 2);
+        let failing = PullRequestKey::new("acme", "widgets", 3);
+        let without_ci = PullRequestKey::new("acme", "widgets", 4);
+        let storage = storage_with("ci-policy", &[&passing, &pending, &failing, &without_ci]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&passing, [PrStatus::Behind])
+            .with_statuses(&pending, [PrStatus::Behind])
+            .with_statuses(&failing, [PrStatus::Behind])
+            .with_statuses(&without_ci, [PrStatus::Behind])
+            .with_ci_state(&passing, CiState::Passing)
+            .with_ci_state(&pending, CiState::Pending)
+            .with_ci_state(&failing, CiState::Failing);
+        let sink = RecordingSink::default();
+
+        storage
+            .set_ci_policy(CiPolicy::OnlyWhenPassing)
+            .await
+            .unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        let mut updated = provider.updated_branches();
+        updated.sort_by_key(|key| key.pr_number);
+        assert_eq!(updated, vec![passing.clone(), without_ci.clone()]);
+
+        storage
+            .set_ci_policy(CiPolicy::NotWhilePending)
+            .await
+            .unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        assert!(provider.updated_branches().contains(&failing));
+        assert!(!provider.updated_branches().contains(&pending));
+        assert_eq!(provider.ci_requests(), 8);
+    }
+
+    #[tokio::test]
+    async fn unreadable_ci_holds_the_update_back_but_keeps_the_status() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("ci-unreadable", &[&key]).await;
+        storage
+            .set_ci_policy(CiPolicy::NotWhilePending)
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Behind])
+            .with_unreadable_ci(&key);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.updated_branches().is_empty());
+        assert_eq!(
+            sink.events(),
+            vec![MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind)]
+        );
+        let stored = &storage.get_open_pull_requests().await.unwrap()[0];
+        assert_eq!(stored.last_status, Some(PrStatus::Behind));
+        assert_eq!(stored.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn ci_is_not_asked_about_without_a_policy() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("ci-always", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Behind])
+            .with_ci_state(&key, CiState::Failing);
+
+        check_pull_requests(&storage, &provider, &RecordingSink::default())
+            .await
+            .unwrap();
+
+        assert_eq!(provider.updated_branches(), vec![key]);
+        assert_eq!(provider.ci_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn updates_over_the_hourly_budget_wait_for_a_later_tick() {
+        let first = PullRequestKey::new("acme", "widgets", 1);
+        let second = PullRequestKey::new("acme", "widgets", 2);
+        let longest_behind = PullRequestKey::new("acme", "widgets", 3);
+        let elsewhere = PullRequestKey::new("acme", "gadgets", 4);
+        let storage = storage_with("budget", &[&first, &second, &longest_behind, &elsewhere]).await;
+        storage
+            .record_status(
+                longest_behind.clone(),
+                PrStatus::Behind,
+                Utc::now() - TimeDelta::days(1),
+            )
+            .await
+            .unwrap();
+        storage.set_max_updates_per_hour(Some(2)).await.unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&first, [PrStatus::Behind])
+            .with_statuses(&second, [PrStatus::Behind])
+            .with_statuses(&longest_behind, [PrStatus::Behind])
+            .with_statuses(&elsewhere, [PrStatus::Behind]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        let mut updated = provider.updated_branches();
+        updated.sort_by_key(|key| key.pr_number);
+        assert_eq!(updated, vec![first, longest_behind, elsewhere.clone()]);
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        assert_eq!(provider.updated_branches().len(), 4);
+        assert!(!provider.updated_branches().contains(&second));
+    }
+
+    #[tokio::test]
+    async fn updates_in_one_repository_are_spaced_apart() {
+        let first = PullRequestKey::new("acme", "widgets", 1);
+        let second = PullRequestKey::new("acme", "widgets", 2);
+        let storage = storage_with("spacing", &[&first, &second]).await;
+        storage.set_update_spacing_minutes(Some(30)).await.unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&first, [PrStatus::Behind])
+            .with_statuses(&second, [PrStatus::Behind]);
+        let sink = RecordingSink::default();
+
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(provider.updated_branches(), vec![first]);
+    }
+
+    #[tokio::test]
+    async fn pull_request_held_back_by_ci_leaves_the_budget_to_the_next_one() {
+        let held_back = PullRequestKey::new("acme", "widgets", 1);
+        let ready = PullRequestKey::new("acme", "widgets", 2);
+        let storage = storage_with("budget-ci", &[&held_back, &ready]).await;
+        storage
+            .record_status(
+                held_back.clone(),
+                PrStatus::Behind,
+                Utc::now() - TimeDelta::days(1),
+            )
+            .await
+            .unwrap();
+        storage.set_update_spacing_minutes(Some(30)).await.unwrap();
+        storage
+            .set_ci_policy(CiPolicy::NotWhilePending)
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&held_back, [PrStatus::Behind])
+            .with_statuses(&ready, [PrStatus::Behind])
+            .with_ci_state(&held_back, CiState::Pending)
+            .with_ci_state(&ready, CiState::Passing);
+
+        check_pull_requests(&storage, &provider, &RecordingSink::default())
+            .await
+            .unwrap();
+
+        assert_eq!(provider.updated_branches(), vec![ready]);
+    }
+
+    #[tokio::test]
+    async fn branches_are_only_updated_once_far_enough_behind() {
+        let slightly_behind = PullRequestKey::new("acme", "widgets", 1);
+        let far_behind = PullRequestKey::new("acme", "widgets", 2);
+        let eager_repo = PullRequestKey::new("acme", "gadgets", 3);
+        let storage =
+            storage_with("behind-by", &[&slightly_behind, &far_behind, &eager_repo]).await;
+        storage.set_behind_threshold(5).await.unwrap();
+        storage
+            .set_repository_behind_threshold("acme".into(), "gadgets".into(), Some(1))
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&slightly_behind, [PrStatus::Behind])
+            .with_statuses(&far_behind, [PrStatus::Behind])
+            .with_statuses(&eager_repo, [PrStatus::Behind])
+            .with_behind_by(&slightly_behind, 2)
+            .with_behind_by(&far_behind, 8);
+
+        check_pull_requests(&storage, &provider, &RecordingSink::default())
+            .await
+            .unwrap();
+
+        let mut updated = provider.updated_branches();
+        updated.sort_by_key(|key| key.pr_number);
+        assert_eq!(updated, vec![far_behind.clone(), eager_repo]);
+        let behind_by: HashMap<_, _> = storage
+            .get_unmerged_pull_requests(DateTime::<Utc>::MIN_UTC)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|pr| (pr.key(), pr.behind_by))
+            .collect();
+        assert_eq!(behind_by[&slightly_behind], Some(2));
+        assert_eq!(behind_by[&far_behind], Some(8));
+    }
+
+    #[tokio::test]
+    async fn transitions_and_branch_updates_are_kept_as_history() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("history", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(
+            &key,
+            [
+                PrStatus::Behind,
+                PrStatus::Behind,
+                PrStatus::UpToDate,
+                PrStatus::Merged,
+            ],
+        );
+        let sink = RecordingSink::default();
+
+        for _ in 0..4 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        let history: Vec<_> = storage
+            .get_pull_request_events(key)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|event| (event.kind, event.status))
+            .collect();
+        assert_eq!(
+            history,
+            vec![
+                (PullRequestEventKind::StatusChanged, Some(PrStatus::Behind)),
+                (PullRequestEventKind::BranchUpdated, None),
+                (PullRequestEventKind::BranchUpdated, None),
+                (
+                    PullRequestEventKind::StatusChanged,
+                    Some(PrStatus::UpToDate)
+                ),
+                (PullRequestEventKind::StatusChanged, Some(PrStatus::Merged)),
+                (PullRequestEventKind::Merged, None),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn pr_update_method_overrides_the_repository_choice() {
+        let repo_rebase = PullRequestKey::new("acme", "widgets", 7);
+        let pr_merge = PullRequestKey::new("acme", "widgets", 8);
+        let default_merge = PullRequestKey::new("acme", "gadgets", 9);
+        let storage =
+            storage_with("update-method", &[&repo_rebase, &pr_merge, &default_merge]).await;
+        storage
+            .set_repository_update_method(
+                "acme".into(),
+                "widgets".into(),
+                Some(UpdateMethod::Rebase),
+            )
+            .await
+            .unwrap();
+        storage
+            .set_pull_request_update_method(pr_merge.clone(), Some(UpdateMethod::Merge))
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&repo_rebase, [PrStatus::Behind])
+            .with_statuses(&pr_merge, [PrStatus::Behind])
+            .with_statuses(&default_merge, [PrStatus::Behind]);
+
+        check_pull_requests(&storage, &provider, &RecordingSink::default())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            provider.update_methods(),
+            HashMap::from([
+                (repo_rebase.clone(), UpdateMethod::Rebase),
+                (pr_merge.clone(), UpdateMethod::Merge),
+                (default_merge.clone(), UpdateMethod::Merge),
+            ])
+        );
+        let actions: HashMap<_, _> = storage
+            .get_audit_log()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.key, entry.action))
+            .collect();
+        assert_eq!(
+            actions,
+            HashMap::from([
+                (repo_rebase, AuditAction::RebaseBranch),
+                (pr_merge, AuditAction::UpdateBranch),
+                (default_merge, AuditAction::UpdateBranch),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn branch_that_moved_since_the_check_is_left_for_the_next_one() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("head-moved", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Behind])
+            .with_moved_head(&key);
+        let sink = RecordingSink::default();
+
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(provider.updated_branches(), vec![key.clone()]);
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                MonitorEvent::BranchUpdated(key.clone()),
+            ]
+        );
+        let results: Vec<_> = storage
+            .get_audit_log()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.result)
+            .collect();
+        assert_eq!(results, vec![AuditResult::Failed, AuditResult::Succeeded]);
+    }
+
+    #[tokio::test]
+    async fn auto_merge_is_turned_back_on_after_a_branch_update() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("auto-merge", &[&key]).await;
+        storage
+            .set_pull_request_auto_merge(key.clone(), Some(MergeMethod::Squash))
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default().with_statuses(
+            &key,
+            [PrStatus::Blocked, PrStatus::Behind, PrStatus::Blocked],
+        );
+        let sink = RecordingSink::default();
+
+        for _ in 0..3 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            provider.auto_merges(),
+            HashMap::from([(key.clone(), MergeMethod::Squash)])
+        );
+        assert!(storage.get_open_pull_requests().await.unwrap()[0].auto_merge_enabled);
+
+        set_auto_merge(&storage, &provider, &key, None)
+            .await
+            .unwrap();
+
+        assert!(provider.auto_merges().is_empty());
+        let actions: Vec<_> = storage
+            .get_audit_log()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.action, entry.result))
+            .collect();
+        assert_eq!(
+            actions,
+            vec![
+                (AuditAction::EnableAutoMerge, AuditResult::Succeeded),
+                (AuditAction::UpdateBranch, AuditResult::Succeeded),
+                (AuditAction::EnableAutoMerge, AuditResult::Succeeded),
+                (AuditAction::DisableAutoMerge, AuditResult::Succeeded),
+            ]
+        );
+        assert!(!storage.get_open_pull_requests().await.unwrap()[0].auto_merge_enabled);
+    }
+
+    #[tokio::test]
+    async fn auto_merge_pull_request_that_can_already_be_merged_is_left_alone() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("auto-merge-now", &[&key]).await;
+        storage
+            .set_pull_request_auto_merge(key.clone(), Some(MergeMethod::Rebase))
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::UpToDate]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.auto_merges().is_empty());
+        assert!(storage.get_audit_log().await.unwrap().is_empty());
+        assert_eq!(
+            sink.events(),
+            vec![MonitorEvent::StatusChecked(key, PrStatus::UpToDate)]
+        );
+    }
+
+    #[tokio::test]
+    async fn auto_merge_of_an_unwatched_pull_request_is_not_set() {
+        let watched = PullRequestKey::new("acme", "widgets", 7);
+        let unwatched = PullRequestKey::new("acme", "widgets", 8);
+        let storage = storage_with("auto-merge-unwatched", &[&watched]).await;
+        let provider = ScriptedProvider::default().with_statuses(&unwatched, [PrStatus::Behind]);
+
+        let err = set_auto_merge(&storage, &provider, &unwatched, Some(MergeMethod::Squash))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            err,
+            AppError::Storage(StorageError::PullRequestNotFound)
+        ));
+        assert_eq!(provider.snapshot_requests(), 0);
+        assert!(provider.auto_merges().is_empty());
+        assert!(storage.get_audit_log().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn queued_pull_request_is_left_to_the_merge_queue() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("queued", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Queued])
+            .with_merge_queue(&key);
+        let sink = RecordingSink::default();
+        storage.set_auto_enqueue(true).await.unwrap();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.updated_branches().is_empty());
+        assert!(provider.enqueued().is_empty());
+        assert_eq!(
+            sink.events(),
+            vec![MonitorEvent::StatusChecked(key.clone(), PrStatus::Queued)]
+        );
+    }
+
+    #[tokio::test]
+    async fn approved_pull_requests_with_passing_ci_are_enqueued() {
+        let ready = PullRequestKey::new("acme", "widgets", 7);
+        let failing = PullRequestKey::new("acme", "widgets", 8);
+        let no_ci = PullRequestKey::new("acme", "widgets", 10);
+        let unapproved = PullRequestKey::new("acme", "widgets", 11);
+        let unreadable = PullRequestKey::new("acme", "widgets", 12);
+        let no_queue = PullRequestKey::new("acme", "gadgets", 9);
+        let storage = storage_with(
+            "enqueue",
+            &[
+                &ready,
+                &failing,
+                &no_ci,
+                &unapproved,
+                &unreadable,
+                &no_queue,
+            ],
+        )
+        .await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&ready, [PrStatus::UpToDate])
+            .with_statuses(&failing, [PrStatus::UpToDate])
+            .with_statuses(&no_ci, [PrStatus::UpToDate])
+            .with_statuses(&unapproved, [PrStatus::Blocked])
+            .with_statuses(&unreadable, [PrStatus::UpToDate])
+            .with_statuses(&no_queue, [PrStatus::UpToDate])
+            .with_merge_queue(&ready)
+            .with_merge_queue(&failing)
+            .with_merge_queue(&no_ci)
+            .with_merge_queue(&unapproved)
+            .with_merge_queue(&unreadable)
+            .with_unreadable_ci(&unreadable)
+            .with_unapproved(&unapproved)
+            .with_ci_state(&ready, CiState::Passing)
+            .with_ci_state(&failing, CiState::Failing)
+            .with_ci_state(&unapproved, CiState::Passing)
+            .with_ci_state(&no_queue, CiState::Passing);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        assert!(provider.enqueued().is_empty());
+
+        storage.set_auto_enqueue(true).await.unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(provider.enqueued(), vec![ready.clone()]);
+        let audit = storage.get_audit_log().await.unwrap();
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].action, AuditAction::Enqueue);
+        assert_eq!(audit[0].result, AuditResult::Succeeded);
+    }
+
+    #[tokio::test]
+    async fn refused_pull_request_is_not_enqueued_again_at_the_same_commit() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("enqueue-refused", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::UpToDate])
+            .with_merge_queue(&key)
+            .with_failing_enqueue(&key)
+            .with_ci_state(&key, CiState::Passing);
+        let sink = RecordingSink::default();
+        storage.set_auto_enqueue(true).await.unwrap();
+
+        for _ in 0..3 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        let audit = storage.get_audit_log().await.unwrap();
+        assert_eq!(audit.len(), 1);
+        assert_eq!(audit[0].action, AuditAction::Enqueue);
+        assert_eq!(audit[0].result, AuditResult::Failed);
+        assert_eq!(audit[0].head_sha_before, "sha-0");
+    }
+
+    #[tokio::test]
+    async fn allowed_failed_jobs_are_rerun_until_the_budget_runs_out() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("reruns", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Blocked])
+            .with_failed_job(&key, "test (ubuntu)", 11)
+            .with_failed_job(&key, "lint", 12);
+        let sink = RecordingSink::default();
+        storage.set_rerun_budget(Some(2)).await.unwrap();
+        storage
+            .set_rerun_job_patterns(vec!["Test *".to_owned()])
+            .await
+            .unwrap();
+
+        for _ in 0..4 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            provider.reruns(),
+            vec![(key.clone(), 11), (key.clone(), 11)]
+        );
+        let exhausted: Vec<_> = sink
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, MonitorEvent::RerunsExhausted(..)))
+            .collect();
+        assert_eq!(
+            exhausted,
+            vec![MonitorEvent::RerunsExhausted(
+                key.clone(),
+                "test (ubuntu)".to_owned()
+            )]
+        );
+        let actions: Vec<_> = storage
+            .get_audit_log()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, vec![AuditAction::RerunFailedJobs; 2]);
+    }
+
+    #[tokio::test]
+    async fn rerun_budget_is_not_reset_by_branch_updates() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("reruns-per-pr", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(
+                &key,
+                [PrStatus::Blocked, PrStatus::Behind, PrStatus::Blocked],
+            )
+            .with_failed_job(&key, "test (ubuntu)", 11);
+        let sink = RecordingSink::default();
+        storage.set_rerun_budget(Some(1)).await.unwrap();
+        storage
+            .set_rerun_job_patterns(vec!["test *".to_owned()])
+            .await
+            .unwrap();
+
+        for _ in 0..3 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(provider.updated_branches(), vec![key.clone()]);
+        assert_eq!(provider.reruns(), vec![(key.clone(), 11)]);
+        assert!(sink.events().contains(&MonitorEvent::RerunsExhausted(
+            key,
+            "test (ubuntu)".to_owned()
+        )));
+    }
+
+    #[tokio::test]
+    async fn each_rerun_workflow_run_is_audited_on_its_own() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("reruns-partial", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Blocked])
+            .with_failed_job(&key, "test (ubuntu)", 11)
+            .with_failed_job(&key, "test (macos)", 12)
+            .with_failing_rerun(12);
+        let sink = RecordingSink::default();
+        storage.set_rerun_budget(Some(3)).await.unwrap();
+        storage
+            .set_rerun_job_patterns(vec!["test *".to_owned()])
+            .await
+            .unwrap();
+
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            provider.reruns(),
+            vec![(key.clone(), 11), (key.clone(), 11)]
+        );
+        let results: Vec<_> = storage
+            .get_audit_log()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.result)
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                AuditResult::Succeeded,
+                AuditResult::Failed,
+                AuditResult::Succeeded
+            ]
+        );
+        let events = storage.get_pull_request_events(key).await.unwrap();
+        assert_eq!(
+            events
+                .iter()
+                .filter(|event| event.kind == PullRequestEventKind::JobsRerun)
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn unreadable_failed_jobs_are_skipped_without_failing_the_check() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("reruns-unreadable", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Blocked])
+            .with_failed_job(&key, "test (ubuntu)", 11)
+            .with_unreadable_ci(&key);
+        let sink = RecordingSink::default();
+        storage.set_rerun_budget(Some(2)).await.unwrap();
+        storage
+            .set_rerun_job_patterns(vec!["test *".to_owned()])
+            .await
+            .unwrap();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.reruns().is_empty());
+        assert!(sink
+            .events()
+            .contains(&MonitorEvent::StatusChecked(key, PrStatus::Blocked)));
+        let stored = &storage.get_open_pull_requests().await.unwrap()[0];
+        assert_eq!(stored.last_status, Some(PrStatus::Blocked));
+        assert_eq!(stored.last_error, None);
+    }
+
+    #[test]
+    fn job_patterns_match_whole_names_with_wildcards() {
+        assert!(job_matches("test *", "Test (ubuntu-latest)"));
+        assert!(job_matches("*e2e*", "build / e2e-chrome"));
+        assert!(!job_matches("test", "test (ubuntu)"));
+        assert!(!job_matches("lint.*", "lint-rust"));
+    }
+
+    #[tokio::test]
+    async fn branch_updates_are_audited_with_the_head_before_and_after() {
+        let updated = PullRequestKey::new("acme", "widgets", 7);
+        let failing = PullRequestKey::new("acme", "widgets", 8);
+        let storage = storage_with("audit", &[&updated, &failing]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&updated, [PrStatus::Behind, PrStatus::UpToDate])
+            .with_statuses(&failing, [PrStatus::Behind])
+            .with_failing_updates(&failing);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        let before_next_check = storage.get_audit_log().await.unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        let audit = storage.get_audit_log().await.unwrap();
+
+        assert_eq!(before_next_check[0].head_sha_after, None);
+        let summary: Vec<_> = audit
+            .iter()
+            .map(|entry| {
+                (
+                    entry.key.pr_number,
+                    entry.result,
+                    entry.head_sha_before.as_str(),
+                    entry.head_sha_after.as_deref(),
+                    entry.error.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (7, AuditResult::Succeeded, "sha-0", Some("sha-1"), None),
+                (
+                    8,
+                    AuditResult::Failed,
+                    "sha-0",
+                    None,
+                    Some("Can't update pr branch")
+                ),
+                (
+                    8,
+                    AuditResult::Failed,
+                    "sha-0",
+                    None,
+                    Some("Can't update pr branch")
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_the_update_without_making_it() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("dry-run", &[&key]).await;
+        storage.set_dry_run(true).await.unwrap();
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Behind]);
+        let sink = RecordingSink::default();
+
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert!(provider.updated_branches().is_empty());
+        assert!(storage.get_audit_log().await.unwrap().is_empty());
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                MonitorEvent::WouldUpdateBranch(key.clone()),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn dry_run_only_reports_updates_the_monitor_would_make() {
+        let far_behind = PullRequestKey::new("acme", "widgets", 1);
+        let slightly_behind = PullRequestKey::new("acme", "widgets", 2);
+        let ci_pending = PullRequestKey::new("acme", "widgets", 3);
+        let storage = storage_with(
+            "dry-run-decisions",
+            &[&far_behind, &slightly_behind, &ci_pending],
+        )
+        .await;
+        storage.set_dry_run(true).await.unwrap();
+        storage.set_behind_threshold(5).await.unwrap();
+        storage
+            .set_ci_policy(CiPolicy::NotWhilePending)
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&far_behind, [PrStatus::Behind])
+            .with_statuses(&slightly_behind, [PrStatus::Behind])
+            .with_statuses(&ci_pending, [PrStatus::Behind])
+            .with_behind_by(&far_behind, 8)
+            .with_behind_by(&slightly_behind, 2)
+            .with_behind_by(&ci_pending, 8)
+            .with_ci_state(&ci_pending, CiState::Pending);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.updated_branches().is_empty());
+        let would_update: Vec<_> = sink
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, MonitorEvent::WouldUpdateBranch(..)))
+            .collect();
+        assert_eq!(
+            would_update,
+            vec![MonitorEvent::WouldUpdateBranch(far_behind)]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_comparison_keeps_the_status_without_updating() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("compare-failed", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Behind])
+            .with_failing_compare(&key);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.updated_branches().is_empty());
+        assert_eq!(
+            sink.events(),
+            vec![MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind)]
+        );
+        let stored = &storage.get_open_pull_requests().await.unwrap()[0];
+        assert_eq!(stored.last_status, Some(PrStatus::Behind));
+        assert_eq!(stored.behind_by, None);
+        assert_eq!(stored.last_error, None);
+    }
+
+    #[tokio::test]
+    async fn notify_only_and_silent_pull_requests_are_never_updated() {
+        let notify_only = PullRequestKey::new("acme", "widgets", 7);
+        let silent = PullRequestKey::new("acme", "widgets", 8);
+        let storage = storage_with("modes", &[&notify_only, &silent]).await;
+        storage
+            .set_pull_request_mode(notify_only.clone(), MonitorMode::NotifyOnly)
+            .await
+            .unwrap();
+        storage
+            .set_pull_request_mode(silent.clone(), MonitorMode::Silent)
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default()
+            .with_statuses(&notify_only, [PrStatus::Behind])
+            .with_statuses(&silent, [PrStatus::Behind, PrStatus::Conflicts]);
+        let sink = RecordingSink::default();
+
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert!(provider.updated_branches().is_empty());
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(notify_only.clone(), PrStatus::Behind),
+                MonitorEvent::NeedsAttention(notify_only.clone(), PrStatus::Behind),
+                MonitorEvent::StatusChecked(silent.clone(), PrStatus::Behind),
+                MonitorEvent::StatusChecked(notify_only.clone(), PrStatus::Behind),
+                MonitorEvent::StatusChecked(silent.clone(), PrStatus::Conflicts),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn all_pull_requests_are_loaded_in_one_request_per_tick() {
+        let first = PullRequestKey::new("acme", "widgets", 7);
+        let second = PullRequestKey::new("acme", "gadgets", 8);
+        let storage = storage_with("batched", &[&first, &second]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&first, [PrStatus::UpToDate])
+            .with_statuses(&second, [PrStatus::UpToDate]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(provider.snapshot_requests(), 1);
+    }
+
+    #[tokio::test]
+    async fn failing_pull_request_does_not_stop_the_others() {
+        let broken = PullRequestKey::new("acme", "deleted", 1);
+        let healthy = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("per-pr-errors", &[&broken, &healthy]).await;
+        let provider = ScriptedProvider::default().with_statuses(&healthy, [PrStatus::Behind]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(provider.updated_branches(), vec![healthy.clone()]);
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::CheckFailed(broken.clone(), "Can't load pr details".to_owned()),
+                MonitorEvent::StatusChecked(healthy.clone(), PrStatus::Behind),
+                MonitorEvent::BranchUpdated(healthy.clone()),
+            ]
+        );
+        let stored = storage.get_open_pull_requests().await.unwrap();
+        assert_eq!(stored[0].key(), broken);
+        assert_eq!(
+            stored[0].last_error.as_deref(),
+            Some("Can't load pr details")
+        );
+        assert_eq!(stored[1].last_error, None);
+    }
+
+    #[tokio::test]
+    async fn merged_pull_requests_are_no_longer_checked() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("closed", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Merged]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Merged),
+                MonitorEvent::PrMerged(key.clone()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn closed_pull_request_is_watched_until_reopened() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("reopened", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Closed, PrStatus::Closed, PrStatus::Behind]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        let closed = storage.get_all_pull_requests().await.unwrap();
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(closed[0].state, PullRequestState::Closed);
+        assert_eq!(provider.updated_branches(), vec![key.clone()]);
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Closed),
+                MonitorEvent::PrClosed(key.clone()),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Closed),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                MonitorEvent::PrReopened(key.clone()),
+                MonitorEvent::BranchUpdated(key.clone()),
+            ]
+        );
+        assert_eq!(
+            storage.get_open_pull_requests().await.unwrap()[0].key(),
+            key
+        );
+    }
+
+    #[tokio::test]
+    async fn pull_request_closed_long_ago_is_no_longer_checked() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("closed-long-ago", &[&key]).await;
+        storage
+            .set_pull_request_state(
+                key.clone(),
+                PullRequestState::Closed,
+                Utc::now() - TimeDelta::days(REOPEN_WINDOW_DAYS + 1),
+            )
+            .await
+            .unwrap();
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Behind]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert!(provider.updated_branches().is_empty());
+        assert!(sink.events().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_branch_update_is_reported_once() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("update-failed", &[&key]).await;
+        let provider = ScriptedProvider::default()
+            .with_statuses(&key, [PrStatus::Behind])
+            .with_failing_updates(&key);
+        let sink = RecordingSink::default();
+
+        for _ in 0..2 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                MonitorEvent::UpdateFailed(key.clone(), "Can't update pr branch".to_owned()),
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+            ]
+        );
+        let history = storage.get_pull_request_events(key).await.unwrap();
+        assert_eq!(history[1].kind, PullRequestEventKind::BranchUpdateFailed);
+        assert_eq!(history[1].detail.as_deref(), Some("Can't update pr branch"));
+    }
+
+    #[tokio::test]
+    async fn blocked_pull_request_needs_attention() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("blocked", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Blocked]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sink.events(),
+            vec![
+                MonitorEvent::StatusChecked(key.clone(), PrStatus::Blocked),
+                MonitorEvent::NeedsAttention(key.clone(), PrStatus::Blocked),
+            ]
+        );
+        let stored = &storage.get_open_pull_requests().await.unwrap()[0];
+        assert_eq!(stored.last_status, Some(PrStatus::Blocked));
+        assert!(stored.last_checked_at.is_some());
+        assert_eq!(stored.status_changed_at, stored.last_checked_at);
+    }
+
+    #[tokio::test]
+    async fn attention_events_are_skipped_when_notifications_are_off() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("quiet", &[&key]).await;
+        storage.set_show_notification(false).await.unwrap();
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Conflicts]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sink.events(),
+            vec![MonitorEvent::StatusChecked(
+                key.clone(),
+                PrStatus::Conflicts
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_attention_status_is_notified_once() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("transitions", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(
+            &key,
+            [PrStatus::Conflicts, PrStatus::Conflicts, PrStatus::Blocked],
+        );
+        let sink = RecordingSink::default();
+
+        for _ in 0..3 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        let attention: Vec<_> = sink
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, MonitorEvent::NeedsAttention(..)))
+            .collect();
+        assert_eq!(
+            attention,
+            vec![
+                MonitorEvent::NeedsAttention(key.clone(), PrStatus::Conflicts),
+                MonitorEvent::NeedsAttention(key.clone(), PrStatus::Blocked),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn passing_through_pending_is_not_a_status_change() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("pending-flap", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(
+            &key,
+            [PrStatus::Conflicts, PrStatus::Pending, PrStatus::Conflicts],
+        );
+        let sink = RecordingSink::default();
+
+        for _ in 0..3 {
+            check_pull_requests(&storage, &provider, &sink)
+                .await
+                .unwrap();
+        }
+
+        let attention: Vec<_> = sink
+            .events()
+            .into_iter()
+            .filter(|event| matches!(event, MonitorEvent::NeedsAttention(..)))
+            .collect();
+        assert_eq!(
+            attention,
+            vec![MonitorEvent::NeedsAttention(
+                key.clone(),
+                PrStatus::Conflicts
+            )]
+        );
+    }
+
+    #[tokio::test]
+    async fn unchanged_attention_status_is_reminded_after_the_interval() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("reminder", &[&key]).await;
+        storage.set_reminder_hours(Some(1)).await.unwrap();
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Conflicts]);
+        let sink = RecordingSink::default();
+        let attention_count = |sink: &RecordingSink| {
+            sink.events()
+                .iter()
+                .filter(|event| matches!(event, MonitorEvent::NeedsAttention(..)))
+                .count()
+        };
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        assert_eq!(attention_count(&sink), 1);
+
+        storage
+            .set_last_notified_at(key.clone(), Utc::now() - chrono::Duration::hours(2))
+            .await
+            .unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+        assert_eq!(attention_count(&sink), 2);
+    }
+
+    #[tokio::test]
+    async fn still_computing_merge_state_never_needs_attention() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("pending", &[&key]).await;
+        let provider = ScriptedProvider::default().with_statuses(&key, [PrStatus::Pending]);
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            sink.events(),
+            vec![MonitorEvent::StatusChecked(key.clone(), PrStatus::Pending)]
+        );
+    }
+
+    #[tokio::test]
+    async fn branch_updates_run_concurrently_up_to_the_limit() {
+        let keys: Vec<_> = (1..=6)
+            .map(|number| PullRequestKey::new("acme", "widgets", number))
+            .collect();
+        let storage = storage_with("concurrency", &keys.iter().collect::<Vec<_>>()).await;
+        storage.set_max_concurrent_checks(3).await.unwrap();
+        let provider = keys
+            .iter()
+            .fold(ScriptedProvider::default(), |provider, key| {
+                provider.with_statuses(key, [PrStatus::Behind])
+            });
+        let sink = RecordingSink::default();
+
+        check_pull_requests(&storage, &provider, &sink)
+            .await
+            .unwrap();
+
+        assert_eq!(provider.peak_concurrent_updates(), 3);
+        assert_eq!(provider.updated_branches(), keys);
+        assert_eq!(
+            sink.events(),
+            keys.iter()
+                .flat_map(|key| [
+                    MonitorEvent::StatusChecked(key.clone(), PrStatus::Behind),
+                    MonitorEvent::BranchUpdated(key.clone()),
+                ])
+                .collect::<Vec<_>>()
+        );
+    }
+
+    fn budget(remaining: u64, reset_at: DateTime<Utc>) -> RateLimit {
+        RateLimit {
+            resource: "graphql".to_owned(),
+            limit: 5000,
+            remaining,
+            reset_at,
+        }
+    }
+
+    #[test]
+    fn checks_pause_only_while_the_budget_is_low_and_not_yet_reset() {
+        let now = Utc::now();
+        let reset_at = now + TimeDelta::minutes(30);
+
+        assert_eq!(paused_until(&budget(0, reset_at), now), Some(reset_at));
+        assert_eq!(
+            paused_until(&budget(RATE_LIMIT_RESERVE, reset_at), now),
+            Some(reset_at)
+        );
+        assert_eq!(paused_until(&budget(4000, reset_at), now), None);
+        assert_eq!(
+            paused_until(&budget(0, now - TimeDelta::minutes(1)), now),
+            None
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn monitor_backs_off_until_the_rate_limit_resets() {
+        let key = PullRequestKey::new("acme", "widgets", 7);
+        let storage = storage_with("rate-limit", &[&key]).await;
+        let reset_at = Utc::now() + TimeDelta::hours(1);
+        let provider = Arc::new(
+            ScriptedProvider::default()
+                .with_statuses(&key, [PrStatus::UpToDate])
+                .with_rate_limit(budget(0, reset_at)),
+        );
+        let monitor = Monitor::default();
+
+        monitor
+            .start(
+                storage,
+                provider.clone(),
+                1,
+                Arc::new(RecordingSink::default()),
+            )
+            .await;
+        // The paused clock skips ahead to the next tick once the first check is done.
+        tokio::time::sleep(std::time::Duration::from_millis(1500)).await;
+        monitor.stop().await;
+
+        assert_eq!(provider.snapshot_requests(), 1);
+        assert_eq!(
+            monitor.rate_limit().and_then(|status| status.paused_until),
+            Some(reset_at)
+        );
+    }
+}
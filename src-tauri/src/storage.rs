@@ -1,12 +1,26 @@
-use crate::domain::{PullRequestKey, PullRequestModel, PullRequestState};
+use crate::domain::{
+    AuditAction, AuditEntry, AuditResult, CachedPullRequest, CiPolicy, MergeMethod, MonitorMode,
+    PrStatus, PullRequestEvent, PullRequestEventKind, PullRequestKey, PullRequestModel,
+    PullRequestSnapshot, PullRequestState, UpdateMethod,
+};
 use crate::error::StorageError;
-use rusqlite::types::Type;
-use rusqlite::{params, Connection, OptionalExtension};
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use rusqlite::types::{Type, Value};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use std::collections::{HashMap, HashSet};
 use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
 
 type StorageResult<T> = Result<T, StorageError>;
 
+const PULL_REQUEST_COLUMNS: &str =
+    "owner, repo, pr_number, title, state, closed_at, url, last_error,
+     last_status, last_checked_at, added_at, merged_at, status_changed_at, last_notified_at,
+     mode, update_method, behind_by, auto_merge, auto_merge_enabled";
+
+/// `closed_at` was written as UTC text before timestamps moved to RFC 3339.
+const LEGACY_CLOSED_AT_FORMAT: &str = "%d/%m/%Y %H:%M";
+
 #[derive(Clone)]
 pub struct Storage {
     conn: Arc<Mutex<Connection>>,
@@ -14,50 +28,29 @@ pub struct Storage {
 
 impl Storage {
     pub fn new(db_path: PathBuf) -> StorageResult<Self> {
-        let conn = Connection::open(db_path)?;
-        Self::migrate(&conn)?;
+        let mut conn = Connection::open(db_path)?;
+        Self::migrate(&mut conn)?;
 
         Ok(Self {
             conn: Arc::new(Mutex::new(conn)),
         })
     }
 
-    fn migrate(conn: &Connection) -> rusqlite::Result<()> {
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS pull_request (
-                id INTEGER PRIMARY KEY,
-                owner TEXT NOT NULL,
-                repo TEXT NOT NULL,
-                pr_number INTEGER NOT NULL,
-                title TEXT NOT NULL,
-                state TEXT NOT NULL,
-                url TEXT NOT NULL,
-                closed_at TEXT
-            );",
-            [],
-        )?;
-        conn.execute(
-            "CREATE TABLE IF NOT EXISTS settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            );",
-            [],
-        )?;
-        conn.execute("DROP TABLE IF EXISTS token;", [])?;
-        conn.execute(
-            "DELETE FROM pull_request
-             WHERE id NOT IN (
-                SELECT MIN(id)
-                FROM pull_request
-                GROUP BY owner, repo, pr_number
-             );",
-            [],
-        )?;
-        conn.execute(
-            "CREATE UNIQUE INDEX IF NOT EXISTS idx_pull_request_identity
-             ON pull_request(owner, repo, pr_number);",
-            [],
-        )?;
+    /// Applies every migration newer than the database's `user_version`, each
+    /// in its own transaction.
+    fn migrate(conn: &mut Connection) -> StorageResult<()> {
+        let found: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        let supported = MIGRATIONS.len() as u32;
+        if found > supported {
+            return Err(StorageError::UnsupportedSchemaVersion { found, supported });
+        }
+
+        for (version, migration) in (1..).zip(MIGRATIONS).skip(found as usize) {
+            let tx = conn.transaction()?;
+            migration(&tx)?;
+            tx.pragma_update(None, "user_version", version)?;
+            tx.commit()?;
+        }
 
         Ok(())
     }
@@ -103,6 +96,135 @@ impl Storage {
             .await
     }
 
+    pub async fn get_max_concurrent_checks(&self) -> StorageResult<usize> {
+        let value = self.get_setting("max_concurrent_checks", "4").await?;
+        Ok(value.parse::<usize>().unwrap_or(4).max(1))
+    }
+
+    pub async fn set_max_concurrent_checks(&self, limit: usize) -> StorageResult<()> {
+        self.set_setting("max_concurrent_checks", limit.to_string())
+            .await
+    }
+
+    /// The mode PRs start in when they are added.
+    pub async fn get_default_mode(&self) -> StorageResult<MonitorMode> {
+        let value = self
+            .get_setting("default_mode", MonitorMode::default().as_str())
+            .await?;
+        Ok(MonitorMode::try_from(value.as_str()).unwrap_or_default())
+    }
+
+    pub async fn set_default_mode(&self, mode: MonitorMode) -> StorageResult<()> {
+        self.set_setting("default_mode", mode.as_str().to_owned())
+            .await
+    }
+
+    /// What CI has to report before a behind branch is updated.
+    pub async fn get_ci_policy(&self) -> StorageResult<CiPolicy> {
+        let value = self
+            .get_setting("ci_policy", CiPolicy::default().as_str())
+            .await?;
+        Ok(CiPolicy::try_from(value.as_str()).unwrap_or_default())
+    }
+
+    pub async fn set_ci_policy(&self, policy: CiPolicy) -> StorageResult<()> {
+        self.set_setting("ci_policy", policy.as_str().to_owned())
+            .await
+    }
+
+    /// How many commits a PR must be behind its base before its branch is updated.
+    pub async fn get_behind_threshold(&self) -> StorageResult<u32> {
+        let value = self.get_setting("behind_threshold", "1").await?;
+        Ok(value.parse::<u32>().unwrap_or(1).max(1))
+    }
+
+    pub async fn set_behind_threshold(&self, commits: u32) -> StorageResult<()> {
+        self.set_setting("behind_threshold", commits.max(1).to_string())
+            .await
+    }
+
+    /// How many branch updates one repository may get per hour. `None` means no limit.
+    pub async fn get_max_updates_per_hour(&self) -> StorageResult<Option<u32>> {
+        let value = self.get_setting("max_updates_per_hour", "0").await?;
+        Ok(value.parse::<u32>().ok().filter(|limit| *limit > 0))
+    }
+
+    pub async fn set_max_updates_per_hour(&self, limit: Option<u32>) -> StorageResult<()> {
+        self.set_setting("max_updates_per_hour", limit.unwrap_or(0).to_string())
+            .await
+    }
+
+    /// How many workflow runs a PR may re-run, per PR across all of its commits.
+    /// `None` turns re-runs off.
+    pub async fn get_rerun_budget(&self) -> StorageResult<Option<u32>> {
+        let value = self.get_setting("rerun_budget", "0").await?;
+        Ok(value.parse::<u32>().ok().filter(|budget| *budget > 0))
+    }
+
+    pub async fn set_rerun_budget(&self, budget: Option<u32>) -> StorageResult<()> {
+        self.set_setting("rerun_budget", budget.unwrap_or(0).to_string())
+            .await
+    }
+
+    /// Names of the jobs that may be re-run, where `*` matches anything.
+    pub async fn get_rerun_job_patterns(&self) -> StorageResult<Vec<String>> {
+        let value = self.get_setting("rerun_job_patterns", "").await?;
+        Ok(value.lines().map(str::to_owned).collect())
+    }
+
+    pub async fn set_rerun_job_patterns(&self, patterns: Vec<String>) -> StorageResult<()> {
+        let patterns: Vec<_> = patterns
+            .iter()
+            .map(|pattern| pattern.trim())
+            .filter(|pattern| !pattern.is_empty())
+            .collect();
+        self.set_setting("rerun_job_patterns", patterns.join("\n"))
+            .await
+    }
+
+    /// Minutes to wait between two branch updates in one repository. `None` means no wait.
+    pub async fn get_update_spacing_minutes(&self) -> StorageResult<Option<u64>> {
+        let value = self.get_setting("update_spacing_minutes", "0").await?;
+        Ok(value.parse::<u64>().ok().filter(|minutes| *minutes > 0))
+    }
+
+    pub async fn set_update_spacing_minutes(&self, minutes: Option<u64>) -> StorageResult<()> {
+        self.set_setting("update_spacing_minutes", minutes.unwrap_or(0).to_string())
+            .await
+    }
+
+    /// While on, the monitor reports what it would change on GitHub instead of changing it.
+    pub async fn get_dry_run(&self) -> StorageResult<bool> {
+        let value = self.get_setting("dry_run", "false").await?;
+        Ok(value.parse::<bool>().unwrap_or(false))
+    }
+
+    pub async fn set_dry_run(&self, dry_run: bool) -> StorageResult<()> {
+        self.set_setting("dry_run", dry_run.to_string()).await
+    }
+
+    /// While on, approved PRs with passing CI are added to their merge queue.
+    pub async fn get_auto_enqueue(&self) -> StorageResult<bool> {
+        let value = self.get_setting("auto_enqueue", "false").await?;
+        Ok(value.parse::<bool>().unwrap_or(false))
+    }
+
+    pub async fn set_auto_enqueue(&self, enabled: bool) -> StorageResult<()> {
+        self.set_setting("auto_enqueue", enabled.to_string()).await
+    }
+
+    /// Hours after which an unchanged status that needs attention is notified again.
+    /// `None` means each transition is notified once.
+    pub async fn get_reminder_hours(&self) -> StorageResult<Option<u64>> {
+        let value = self.get_setting("reminder_hours", "0").await?;
+        Ok(value.parse::<u64>().ok().filter(|hours| *hours > 0))
+    }
+
+    pub async fn set_reminder_hours(&self, hours: Option<u64>) -> StorageResult<()> {
+        self.set_setting("reminder_hours", hours.unwrap_or(0).to_string())
+            .await
+    }
+
     async fn get_setting(&self, key: &'static str, default: &'static str) -> StorageResult<String> {
         self.with_conn(move |conn| {
             let value = conn
@@ -149,8 +271,10 @@ impl Storage {
             }
 
             conn.execute(
-                "INSERT INTO pull_request (owner, repo, pr_number, title, state, url, closed_at)
-                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                "INSERT INTO pull_request
+                    (owner, repo, pr_number, title, state, url, closed_at, added_at, merged_at,
+                     mode, update_method)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
                 params![
                     pull_request.owner,
                     pull_request.repo,
@@ -158,7 +282,11 @@ impl Storage {
                     pull_request.title,
                     pull_request.state.as_str(),
                     pull_request.url,
-                    pull_request.closed_at
+                    pull_request.closed_at.map(to_db_time),
+                    to_db_time(pull_request.added_at.unwrap_or_else(Utc::now)),
+                    pull_request.merged_at.map(to_db_time),
+                    pull_request.mode.as_str(),
+                    pull_request.update_method.map(UpdateMethod::as_str)
                 ],
             )?;
 
@@ -174,19 +302,38 @@ impl Storage {
                  WHERE owner = ? AND repo = ? AND pr_number = ?",
                 params![key.owner, key.repo, key.pr_number],
             )?;
+            conn.execute(
+                "DELETE FROM pull_request_etag
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![key.owner, key.repo, key.pr_number],
+            )?;
+            conn.execute(
+                "DELETE FROM pull_request_event
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![key.owner, key.repo, key.pr_number],
+            )?;
             Ok(())
         })
         .await
     }
 
-    pub async fn mark_pull_request_closed(&self, key: PullRequestKey) -> StorageResult<()> {
+    /// Moves a PR to `state` at `at`. Reopening clears the old `closed_at`.
+    pub async fn set_pull_request_state(
+        &self,
+        key: PullRequestKey,
+        state: PullRequestState,
+        at: DateTime<Utc>,
+    ) -> StorageResult<()> {
         self.with_conn(move |conn| {
             conn.execute(
                 "UPDATE pull_request
-                 SET state = ?
-                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                 SET state = ?1,
+                     closed_at = CASE WHEN ?1 = 'open' THEN NULL ELSE COALESCE(closed_at, ?2) END,
+                     merged_at = CASE WHEN ?1 = 'merged' THEN COALESCE(merged_at, ?2) ELSE merged_at END
+                 WHERE owner = ?3 AND repo = ?4 AND pr_number = ?5",
                 params![
-                    PullRequestState::Closed.as_str(),
+                    state.as_str(),
+                    to_db_time(at),
                     key.owner,
                     key.repo,
                     key.pr_number
@@ -197,136 +344,1031 @@ impl Storage {
         .await
     }
 
-    pub async fn get_open_pull_requests(&self) -> StorageResult<Vec<PullRequestModel>> {
-        self.list_pull_requests(Some(PullRequestState::Open)).await
+    /// Stores what a check found. `status_changed_at` only moves when the status does.
+    pub async fn record_status(
+        &self,
+        key: PullRequestKey,
+        status: PrStatus,
+        checked_at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE pull_request
+                 SET status_changed_at = CASE
+                         WHEN last_status IS ?1 THEN status_changed_at
+                         ELSE ?2
+                     END,
+                     last_status = ?1,
+                     last_checked_at = ?2
+                 WHERE owner = ?3 AND repo = ?4 AND pr_number = ?5",
+                params![
+                    status.as_str(),
+                    to_db_time(checked_at),
+                    key.owner,
+                    key.repo,
+                    key.pr_number
+                ],
+            )?;
+            Ok(())
+        })
+        .await
     }
 
-    pub async fn get_all_pull_requests(&self) -> StorageResult<Vec<PullRequestModel>> {
-        self.list_pull_requests(None).await
+    pub async fn set_pull_request_mode(
+        &self,
+        key: PullRequestKey,
+        mode: MonitorMode,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            let changed = conn.execute(
+                "UPDATE pull_request
+                 SET mode = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![mode.as_str(), key.owner, key.repo, key.pr_number],
+            )?;
+            if changed == 0 {
+                return Err(StorageError::PullRequestNotFound);
+            }
+            Ok(())
+        })
+        .await
     }
 
-    async fn list_pull_requests(
+    /// Sets the PR's own update method, or `None` to follow its repository.
+    pub async fn set_pull_request_update_method(
         &self,
-        state: Option<PullRequestState>,
-    ) -> StorageResult<Vec<PullRequestModel>> {
+        key: PullRequestKey,
+        method: Option<UpdateMethod>,
+    ) -> StorageResult<()> {
         self.with_conn(move |conn| {
-            let mut pull_requests = Vec::new();
-
-            if let Some(state) = state {
-                let mut stmt = conn.prepare(
-                    "SELECT owner, repo, pr_number, title, state, closed_at, url
-                     FROM pull_request
-                     WHERE state = ?
-                     ORDER BY owner, repo, pr_number",
-                )?;
-                let mut rows = stmt.query(params![state.as_str()])?;
-                while let Some(row) = rows.next()? {
-                    pull_requests.push(row_to_pull_request(row)?);
-                }
-            } else {
-                let mut stmt = conn.prepare(
-                    "SELECT owner, repo, pr_number, title, state, closed_at, url
-                     FROM pull_request
-                     ORDER BY owner, repo, pr_number",
-                )?;
-                let mut rows = stmt.query([])?;
-                while let Some(row) = rows.next()? {
-                    pull_requests.push(row_to_pull_request(row)?);
-                }
+            let changed = conn.execute(
+                "UPDATE pull_request
+                 SET update_method = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![
+                    method.map(UpdateMethod::as_str),
+                    key.owner,
+                    key.repo,
+                    key.pr_number
+                ],
+            )?;
+            if changed == 0 {
+                return Err(StorageError::PullRequestNotFound);
             }
-
-            Ok(pull_requests)
+            Ok(())
         })
         .await
     }
-}
-
-fn row_to_pull_request(row: &rusqlite::Row<'_>) -> rusqlite::Result<PullRequestModel> {
-    let state_text: String = row.get(4)?;
-    let state = PullRequestState::try_from(state_text.as_str())
-        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(4, Type::Text, Box::new(err)))?;
-
-    Ok(PullRequestModel {
-        owner: row.get(0)?,
-        repo: row.get(1)?,
-        pr_number: row.get(2)?,
-        title: row.get(3)?,
-        state,
-        closed_at: row.get(5)?,
-        url: row.get(6)?,
-    })
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::time::{SystemTime, UNIX_EPOCH};
+    /// Keeps auto-merge on for the PR with `method`, or `None` to stop doing so.
+    pub async fn set_pull_request_auto_merge(
+        &self,
+        key: PullRequestKey,
+        method: Option<MergeMethod>,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            let changed = conn.execute(
+                "UPDATE pull_request
+                 SET auto_merge = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![
+                    method.map(MergeMethod::as_str),
+                    key.owner,
+                    key.repo,
+                    key.pr_number
+                ],
+            )?;
+            if changed == 0 {
+                return Err(StorageError::PullRequestNotFound);
+            }
+            Ok(())
+        })
+        .await
+    }
 
-    fn temp_db_path(name: &str) -> PathBuf {
-        let nanos = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("system time should be after unix epoch")
-            .as_nanos();
-        std::env::temp_dir().join(format!("pr-monitor-{name}-{nanos}.db"))
+    /// Remembers whether GitHub had auto-merge on for the PR.
+    pub async fn set_auto_merge_enabled(
+        &self,
+        key: PullRequestKey,
+        enabled: bool,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE pull_request
+                 SET auto_merge_enabled = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![enabled, key.owner, key.repo, key.pr_number],
+            )?;
+            Ok(())
+        })
+        .await
     }
 
-    fn pull_request(owner: &str, repo: &str, pr_number: u64) -> PullRequestModel {
-        PullRequestModel {
-            owner: owner.to_owned(),
-            repo: repo.to_owned(),
-            pr_number,
-            title: format!("{owner}/{repo}#{pr_number}"),
-            state: PullRequestState::Open,
-            closed_at: None,
-            url: format!("https://api.github.com/repos/{owner}/{repo}/pulls/{pr_number}"),
-        }
+    /// Sets the update method for the repository's PRs that don't have their own.
+    /// `None` goes back to the default.
+    pub async fn set_repository_update_method(
+        &self,
+        owner: String,
+        repo: String,
+        method: Option<UpdateMethod>,
+    ) -> StorageResult<()> {
+        self.set_repository_setting(
+            owner,
+            repo,
+            "update_method",
+            method.map(|method| method.as_str().to_owned().into()),
+        )
+        .await
     }
 
-    #[tokio::test]
-    async fn settings_return_defaults_when_not_configured() {
-        let storage = Storage::new(temp_db_path("defaults")).expect("storage should initialize");
+    /// Update methods chosen per repository, keyed by `(owner, repo)`.
+    pub async fn get_repository_update_methods(
+        &self,
+    ) -> StorageResult<HashMap<(String, String), UpdateMethod>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT owner, repo, update_method
+                 FROM repository_settings
+                 WHERE update_method IS NOT NULL",
+            )?;
+            let mut rows = stmt.query([])?;
+            let mut methods = HashMap::new();
+            while let Some(row) = rows.next()? {
+                let method: String = row.get(2)?;
+                methods.insert(
+                    (row.get(0)?, row.get(1)?),
+                    UpdateMethod::try_from(method.as_str())?,
+                );
+            }
 
-        assert_eq!(storage.get_theme().await.unwrap(), "system");
-        assert_eq!(storage.get_refresh_time().await.unwrap(), 300);
-        assert!(storage.get_show_notification().await.unwrap());
+            Ok(methods)
+        })
+        .await
     }
 
-    #[tokio::test]
-    async fn duplicate_pull_requests_are_scoped_by_repository() {
-        let storage = Storage::new(temp_db_path("identity")).expect("storage should initialize");
-
-        storage
-            .add_pull_request(pull_request("owner-a", "repo", 7))
-            .await
-            .unwrap();
-        storage
-            .add_pull_request(pull_request("owner-b", "repo", 7))
+    /// Overrides how far behind the repository's PRs get before they are updated.
+    /// `None` goes back to the global threshold.
+    pub async fn set_repository_behind_threshold(
+        &self,
+        owner: String,
+        repo: String,
+        commits: Option<u32>,
+    ) -> StorageResult<()> {
+        self.set_repository_setting(owner, repo, "behind_threshold", commits.map(Into::into))
             .await
-            .unwrap();
+    }
 
-        let duplicate = storage
-            .add_pull_request(pull_request("owner-a", "repo", 7))
-            .await
-            .unwrap_err();
+    /// Behind thresholds chosen per repository, keyed by `(owner, repo)`.
+    pub async fn get_repository_behind_thresholds(
+        &self,
+    ) -> StorageResult<HashMap<(String, String), u32>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT owner, repo, behind_threshold
+                 FROM repository_settings
+                 WHERE behind_threshold IS NOT NULL",
+            )?;
+            let mut rows = stmt.query([])?;
+            let mut thresholds = HashMap::new();
+            while let Some(row) = rows.next()? {
+                thresholds.insert((row.get(0)?, row.get(1)?), row.get(2)?);
+            }
 
-        assert!(matches!(duplicate, StorageError::PullRequestAlreadyExists));
+            Ok(thresholds)
+        })
+        .await
     }
 
-    #[tokio::test]
-    async fn delete_pull_request_uses_repository_identity() {
-        let storage = Storage::new(temp_db_path("delete")).expect("storage should initialize");
-
-        storage
-            .add_pull_request(pull_request("owner-a", "repo", 7))
-            .await
-            .unwrap();
-        storage
-            .add_pull_request(pull_request("owner-b", "repo", 7))
-            .await
-            .unwrap();
+    /// Sets one column of a repository's settings, dropping the row once
+    /// nothing is overridden anymore.
+    async fn set_repository_setting(
+        &self,
+        owner: String,
+        repo: String,
+        column: &'static str,
+        value: Option<Value>,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                &format!(
+                    "INSERT INTO repository_settings (owner, repo, {column})
+                     VALUES (?1, ?2, ?3)
+                     ON CONFLICT (owner, repo) DO UPDATE SET {column} = ?3"
+                ),
+                params![owner, repo, value],
+            )?;
+            conn.execute(
+                "DELETE FROM repository_settings
+                 WHERE owner = ? AND repo = ?
+                   AND update_method IS NULL AND behind_threshold IS NULL",
+                params![owner, repo],
+            )?;
+            Ok(())
+        })
+        .await
+    }
 
-        storage
-            .delete_pull_request(PullRequestKey::new("owner-a", "repo", 7))
+    /// Remembers how many commits the PR was behind its base at the last check.
+    pub async fn set_behind_by(
+        &self,
+        key: PullRequestKey,
+        behind_by: Option<u32>,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE pull_request
+                 SET behind_by = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![behind_by, key.owner, key.repo, key.pr_number],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn set_last_error(
+        &self,
+        key: PullRequestKey,
+        error: Option<String>,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE pull_request
+                 SET last_error = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![error, key.owner, key.repo, key.pr_number],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn set_last_notified_at(
+        &self,
+        key: PullRequestKey,
+        at: DateTime<Utc>,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE pull_request
+                 SET last_notified_at = ?
+                 WHERE owner = ? AND repo = ? AND pr_number = ?",
+                params![to_db_time(at), key.owner, key.repo, key.pr_number],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn get_cached_pull_request(
+        &self,
+        key: PullRequestKey,
+    ) -> StorageResult<Option<CachedPullRequest>> {
+        self.with_conn(move |conn| {
+            let row = conn
+                .query_row(
+                    "SELECT etag, title, state, status, head_sha, base_ref
+                     FROM pull_request_etag
+                     WHERE owner = ? AND repo = ? AND pr_number = ?",
+                    params![key.owner, key.repo, key.pr_number],
+                    |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, String>(3)?,
+                            row.get::<_, String>(4)?,
+                            row.get::<_, String>(5)?,
+                        ))
+                    },
+                )
+                .optional()?;
+
+            let Some((etag, title, state, status, head_sha, base_ref)) = row else {
+                return Ok(None);
+            };
+
+            Ok(Some(CachedPullRequest {
+                etag,
+                snapshot: PullRequestSnapshot {
+                    title,
+                    state: PullRequestState::try_from(state.as_str())?,
+                    status: PrStatus::try_from(status.as_str())?,
+                    head_sha,
+                    base_ref,
+                    auto_merge_enabled: None,
+                    merge_queue: None,
+                    approved: None,
+                },
+            }))
+        })
+        .await
+    }
+
+    pub async fn set_cached_pull_request(
+        &self,
+        key: PullRequestKey,
+        cached: CachedPullRequest,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO pull_request_etag
+                    (owner, repo, pr_number, etag, title, state, status, head_sha,
+                     base_ref)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    key.owner,
+                    key.repo,
+                    key.pr_number,
+                    cached.etag,
+                    cached.snapshot.title,
+                    cached.snapshot.state.as_str(),
+                    cached.snapshot.status.as_str(),
+                    cached.snapshot.head_sha,
+                    cached.snapshot.base_ref
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn record_event(
+        &self,
+        key: PullRequestKey,
+        event: PullRequestEvent,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO pull_request_event (owner, repo, pr_number, kind, status, detail, at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    key.owner,
+                    key.repo,
+                    key.pr_number,
+                    event.kind.as_str(),
+                    event.status.map(PrStatus::as_str),
+                    event.detail,
+                    to_db_time(event.at)
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// The PR's history, oldest first.
+    pub async fn get_pull_request_events(
+        &self,
+        key: PullRequestKey,
+    ) -> StorageResult<Vec<PullRequestEvent>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT kind, status, detail, at
+                 FROM pull_request_event
+                 WHERE owner = ? AND repo = ? AND pr_number = ?
+                 ORDER BY at, id",
+            )?;
+            let mut rows = stmt.query(params![key.owner, key.repo, key.pr_number])?;
+            let mut events = Vec::new();
+            while let Some(row) = rows.next()? {
+                let kind: String = row.get(0)?;
+                let status: Option<String> = row.get(1)?;
+                events.push(PullRequestEvent {
+                    kind: PullRequestEventKind::try_from(kind.as_str())?,
+                    status: status
+                        .map(|status| PrStatus::try_from(status.as_str()))
+                        .transpose()?,
+                    detail: row.get(2)?,
+                    at: parse_time(3, &row.get::<_, String>(3)?)?,
+                });
+            }
+
+            Ok(events)
+        })
+        .await
+    }
+
+    pub async fn record_audit_entry(&self, entry: AuditEntry) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "INSERT INTO audit_log
+                    (owner, repo, pr_number, action, head_sha_before, head_sha_after,
+                     result, error, at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                params![
+                    entry.key.owner,
+                    entry.key.repo,
+                    entry.key.pr_number,
+                    entry.action.as_str(),
+                    entry.head_sha_before,
+                    entry.head_sha_after,
+                    entry.result.as_str(),
+                    entry.error,
+                    to_db_time(entry.at)
+                ],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// When each repository's branches were successfully updated since `since`,
+    /// oldest first, keyed by `(owner, repo)`.
+    pub async fn get_branch_updates_since(
+        &self,
+        since: DateTime<Utc>,
+    ) -> StorageResult<HashMap<(String, String), Vec<DateTime<Utc>>>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT owner, repo, at
+                 FROM audit_log
+                 WHERE action IN (?, ?) AND result = ? AND at >= ?
+                 ORDER BY at, id",
+            )?;
+            let mut rows = stmt.query(params![
+                AuditAction::UpdateBranch.as_str(),
+                AuditAction::RebaseBranch.as_str(),
+                AuditResult::Succeeded.as_str(),
+                to_db_time(since)
+            ])?;
+            let mut updates: HashMap<_, Vec<_>> = HashMap::new();
+            while let Some(row) = rows.next()? {
+                updates
+                    .entry((row.get(0)?, row.get(1)?))
+                    .or_default()
+                    .push(parse_time(2, &row.get::<_, String>(2)?)?);
+            }
+
+            Ok(updates)
+        })
+        .await
+    }
+
+    /// The head commits each PR was added to its merge queue at, or tried to be.
+    pub async fn get_enqueued_heads(&self) -> StorageResult<HashSet<(PullRequestKey, String)>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT DISTINCT owner, repo, pr_number, head_sha_before
+                 FROM audit_log
+                 WHERE action = ?",
+            )?;
+            let rows = stmt.query_map(params![AuditAction::Enqueue.as_str()], |row| {
+                let key = PullRequestKey::new(
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                );
+                Ok((key, row.get(3)?))
+            })?;
+
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        })
+        .await
+    }
+
+    /// How many workflow runs each PR tried to re-run, across all its commits.
+    /// Re-runs GitHub refused count too.
+    pub async fn get_rerun_counts(&self) -> StorageResult<HashMap<PullRequestKey, u32>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT owner, repo, pr_number, COUNT(*)
+                 FROM audit_log
+                 WHERE action = ?
+                 GROUP BY owner, repo, pr_number",
+            )?;
+            let rows = stmt.query_map(params![AuditAction::RerunFailedJobs.as_str()], |row| {
+                let key = PullRequestKey::new(
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get(2)?,
+                );
+                Ok((key, row.get(3)?))
+            })?;
+
+            Ok(rows.collect::<rusqlite::Result<_>>()?)
+        })
+        .await
+    }
+
+    /// Fills in `head_sha_after` on successful writes once the PR's head has
+    /// moved away from where it was when the write was made.
+    pub async fn complete_audit_entries(
+        &self,
+        key: PullRequestKey,
+        head_sha: String,
+    ) -> StorageResult<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "UPDATE audit_log
+                 SET head_sha_after = ?1
+                 WHERE owner = ?2 AND repo = ?3 AND pr_number = ?4
+                   AND result = 'succeeded'
+                   AND head_sha_after IS NULL
+                   AND head_sha_before != ?1",
+                params![head_sha, key.owner, key.repo, key.pr_number],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Every audit entry, oldest first. Entries outlive the PRs they are about.
+    pub async fn get_audit_log(&self) -> StorageResult<Vec<AuditEntry>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT owner, repo, pr_number, action, head_sha_before, head_sha_after,
+                        result, error, at
+                 FROM audit_log
+                 ORDER BY at, id",
+            )?;
+            let mut rows = stmt.query([])?;
+            let mut entries = Vec::new();
+            while let Some(row) = rows.next()? {
+                let action: String = row.get(3)?;
+                let result: String = row.get(6)?;
+                entries.push(AuditEntry {
+                    key: PullRequestKey::new(
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get(2)?,
+                    ),
+                    action: AuditAction::try_from(action.as_str())?,
+                    head_sha_before: row.get(4)?,
+                    head_sha_after: row.get(5)?,
+                    result: AuditResult::try_from(result.as_str())?,
+                    error: row.get(7)?,
+                    at: parse_time(8, &row.get::<_, String>(8)?)?,
+                });
+            }
+
+            Ok(entries)
+        })
+        .await
+    }
+
+    pub async fn get_open_pull_requests(&self) -> StorageResult<Vec<PullRequestModel>> {
+        self.list_pull_requests(&[PullRequestState::Open]).await
+    }
+
+    /// Open PRs plus the ones closed without merging since `closed_since`,
+    /// which can still be reopened.
+    ///
+    /// PRs stored before merged ones were told apart from closed ones have no
+    /// `added_at`, and may be merged even though they read as closed. They are
+    /// included until their first check settles which one they are.
+    pub async fn get_unmerged_pull_requests(
+        &self,
+        closed_since: DateTime<Utc>,
+    ) -> StorageResult<Vec<PullRequestModel>> {
+        self.with_conn(move |conn| {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {PULL_REQUEST_COLUMNS}
+                 FROM pull_request
+                 WHERE state = 'open'
+                    OR (state = 'closed' AND (closed_at IS NULL OR closed_at >= ?))
+                    OR (state = 'closed' AND added_at IS NULL AND last_checked_at IS NULL)
+                 ORDER BY owner, repo, pr_number"
+            ))?;
+            let mut rows = stmt.query(params![to_db_time(closed_since)])?;
+            let mut pull_requests = Vec::new();
+            while let Some(row) = rows.next()? {
+                pull_requests.push(row_to_pull_request(row)?);
+            }
+
+            Ok(pull_requests)
+        })
+        .await
+    }
+
+    pub async fn get_all_pull_requests(&self) -> StorageResult<Vec<PullRequestModel>> {
+        self.list_pull_requests(&[]).await
+    }
+
+    /// Lists the PRs in any of `states`, or every PR when `states` is empty.
+    async fn list_pull_requests(
+        &self,
+        states: &'static [PullRequestState],
+    ) -> StorageResult<Vec<PullRequestModel>> {
+        self.with_conn(move |conn| {
+            let mut pull_requests = Vec::new();
+
+            if !states.is_empty() {
+                let placeholders = vec!["?"; states.len()].join(", ");
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {PULL_REQUEST_COLUMNS}
+                     FROM pull_request
+                     WHERE state IN ({placeholders})
+                     ORDER BY owner, repo, pr_number"
+                ))?;
+                let mut rows =
+                    stmt.query(params_from_iter(states.iter().map(|state| state.as_str())))?;
+                while let Some(row) = rows.next()? {
+                    pull_requests.push(row_to_pull_request(row)?);
+                }
+            } else {
+                let mut stmt = conn.prepare(&format!(
+                    "SELECT {PULL_REQUEST_COLUMNS}
+                     FROM pull_request
+                     ORDER BY owner, repo, pr_number"
+                ))?;
+                let mut rows = stmt.query([])?;
+                while let Some(row) = rows.next()? {
+                    pull_requests.push(row_to_pull_request(row)?);
+                }
+            }
+
+            Ok(pull_requests)
+        })
+        .await
+    }
+}
+
+fn row_to_pull_request(row: &rusqlite::Row<'_>) -> rusqlite::Result<PullRequestModel> {
+    let state_text: String = row.get(4)?;
+    let state = PullRequestState::try_from(state_text.as_str())
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(4, Type::Text, Box::new(err)))?;
+
+    let mode_text: String = row.get(14)?;
+    let mode = MonitorMode::try_from(mode_text.as_str())
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(14, Type::Text, Box::new(err)))?;
+
+    let update_method = row
+        .get::<_, Option<String>>(15)?
+        .map(|method| {
+            UpdateMethod::try_from(method.as_str()).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(15, Type::Text, Box::new(err))
+            })
+        })
+        .transpose()?;
+
+    let auto_merge = row
+        .get::<_, Option<String>>(17)?
+        .map(|method| {
+            MergeMethod::try_from(method.as_str()).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(17, Type::Text, Box::new(err))
+            })
+        })
+        .transpose()?;
+
+    let last_status = row
+        .get::<_, Option<String>>(8)?
+        .map(|status| {
+            PrStatus::try_from(status.as_str()).map_err(|err| {
+                rusqlite::Error::FromSqlConversionFailure(8, Type::Text, Box::new(err))
+            })
+        })
+        .transpose()?;
+
+    Ok(PullRequestModel {
+        owner: row.get(0)?,
+        repo: row.get(1)?,
+        pr_number: row.get(2)?,
+        title: row.get(3)?,
+        state,
+        closed_at: time_column(row, 5)?,
+        url: row.get(6)?,
+        last_error: row.get(7)?,
+        last_status,
+        last_checked_at: time_column(row, 9)?,
+        added_at: time_column(row, 10)?,
+        merged_at: time_column(row, 11)?,
+        status_changed_at: time_column(row, 12)?,
+        last_notified_at: time_column(row, 13)?,
+        mode,
+        update_method,
+        behind_by: row.get(16)?,
+        auto_merge,
+        auto_merge_enabled: row.get(18)?,
+    })
+}
+
+fn to_db_time(at: DateTime<Utc>) -> String {
+    at.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn time_column(row: &rusqlite::Row<'_>, index: usize) -> rusqlite::Result<Option<DateTime<Utc>>> {
+    row.get::<_, Option<String>>(index)?
+        .map(|text| parse_time(index, &text))
+        .transpose()
+}
+
+fn parse_time(index: usize, text: &str) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(text)
+        .map(|at| at.with_timezone(&Utc))
+        .map_err(|err| rusqlite::Error::FromSqlConversionFailure(index, Type::Text, Box::new(err)))
+}
+
+/// Rewrites `closed_at` values saved in the old text format as RFC 3339.
+/// Values that fit neither format are dropped rather than failing every read.
+fn migrate_legacy_closed_at(conn: &Connection) -> rusqlite::Result<()> {
+    let mut stmt =
+        conn.prepare("SELECT id, closed_at FROM pull_request WHERE closed_at IS NOT NULL")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    for (id, closed_at) in rows {
+        if DateTime::parse_from_rfc3339(&closed_at).is_ok() {
+            continue;
+        }
+
+        let converted = NaiveDateTime::parse_from_str(&closed_at, LEGACY_CLOSED_AT_FORMAT)
+            .ok()
+            .map(|closed_at| to_db_time(closed_at.and_utc()));
+        conn.execute(
+            "UPDATE pull_request SET closed_at = ? WHERE id = ?",
+            params![converted, id],
+        )?;
+    }
+
+    Ok(())
+}
+
+type Migration = fn(&Connection) -> rusqlite::Result<()>;
+
+/// Schema steps in order; a database at `user_version` N has run the first N.
+///
+/// Databases created before versioning report version 0 in either of the
+/// shapes the baseline left behind, so the steps up to `dedupe_pull_requests`
+/// check before they change anything. Later steps can assume the previous one
+/// has run.
+const MIGRATIONS: &[Migration] = &[
+    create_base_tables,
+    dedupe_pull_requests,
+    add_status_tracking,
+    create_event_history,
+    create_audit_log,
+    add_monitor_mode,
+    add_update_methods,
+    add_auto_merge,
+];
+
+fn create_base_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS pull_request (
+            id INTEGER PRIMARY KEY,
+            owner TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            pr_number INTEGER NOT NULL,
+            title TEXT NOT NULL,
+            state TEXT NOT NULL,
+            url TEXT NOT NULL,
+            closed_at TEXT
+        );
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        DROP TABLE IF EXISTS token;",
+    )
+}
+
+fn dedupe_pull_requests(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "DELETE FROM pull_request
+         WHERE id NOT IN (
+            SELECT MIN(id)
+            FROM pull_request
+            GROUP BY owner, repo, pr_number
+         );
+         CREATE UNIQUE INDEX IF NOT EXISTS idx_pull_request_identity
+         ON pull_request(owner, repo, pr_number);",
+    )
+}
+
+/// Adds what the monitor keeps about each PR between checks, and the cache of
+/// answers to conditional requests.
+fn add_status_tracking(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE pull_request ADD COLUMN last_error TEXT;
+        ALTER TABLE pull_request ADD COLUMN last_status TEXT;
+        ALTER TABLE pull_request ADD COLUMN last_checked_at TEXT;
+        ALTER TABLE pull_request ADD COLUMN added_at TEXT;
+        ALTER TABLE pull_request ADD COLUMN merged_at TEXT;
+        ALTER TABLE pull_request ADD COLUMN status_changed_at TEXT;
+        ALTER TABLE pull_request ADD COLUMN last_notified_at TEXT;
+        CREATE TABLE pull_request_etag (
+            owner TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            pr_number INTEGER NOT NULL,
+            etag TEXT NOT NULL,
+            title TEXT NOT NULL,
+            state TEXT NOT NULL,
+            status TEXT NOT NULL,
+            head_sha TEXT NOT NULL,
+            base_ref TEXT NOT NULL,
+            PRIMARY KEY (owner, repo, pr_number)
+        );",
+    )?;
+    migrate_legacy_closed_at(conn)
+}
+
+fn create_event_history(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE pull_request_event (
+            id INTEGER PRIMARY KEY,
+            owner TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            pr_number INTEGER NOT NULL,
+            kind TEXT NOT NULL,
+            status TEXT,
+            detail TEXT,
+            at TEXT NOT NULL
+        );
+        CREATE INDEX idx_pull_request_event_pr
+        ON pull_request_event(owner, repo, pr_number, at);",
+    )
+}
+
+fn create_audit_log(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE audit_log (
+            id INTEGER PRIMARY KEY,
+            owner TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            pr_number INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            head_sha_before TEXT NOT NULL,
+            head_sha_after TEXT,
+            result TEXT NOT NULL,
+            error TEXT,
+            at TEXT NOT NULL
+        );",
+    )
+}
+
+fn add_monitor_mode(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE pull_request ADD COLUMN mode TEXT NOT NULL DEFAULT 'auto_update';",
+    )
+}
+
+/// A repository can override the update method and the behind threshold
+/// separately, so either may be unset.
+fn add_update_methods(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE pull_request ADD COLUMN update_method TEXT;
+        ALTER TABLE pull_request ADD COLUMN behind_by INTEGER;
+        CREATE TABLE repository_settings (
+            owner TEXT NOT NULL,
+            repo TEXT NOT NULL,
+            update_method TEXT,
+            behind_threshold INTEGER,
+            PRIMARY KEY (owner, repo)
+        );",
+    )
+}
+
+fn add_auto_merge(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "ALTER TABLE pull_request ADD COLUMN auto_merge TEXT;
+        ALTER TABLE pull_request ADD COLUMN auto_merge_enabled INTEGER NOT NULL DEFAULT 0;",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    fn temp_db_path(name: &str) -> PathBuf {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time should be after unix epoch")
+            .as_nanos();
+        std::env::temp_dir().join(format!("pr-monitor-{name}-{nanos}.db"))
+    }
+
+    fn added_at() -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap()
+    }
+
+    fn pull_request(owner: &str, repo: &str, pr_number: u64) -> PullRequestModel {
+        PullRequestModel {
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+            pr_number,
+            title: format!("{owner}/{repo}#{pr_number}"),
+            state: PullRequestState::Open,
+            closed_at: None,
+            url: format!("https://api.github.com/repos/{owner}/{repo}/pulls/{pr_number}"),
+            last_error: None,
+            last_status: None,
+            last_checked_at: None,
+            added_at: Some(added_at()),
+            merged_at: None,
+            status_changed_at: None,
+            last_notified_at: None,
+            mode: MonitorMode::AutoUpdate,
+            update_method: None,
+            behind_by: None,
+            auto_merge: None,
+            auto_merge_enabled: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn settings_return_defaults_when_not_configured() {
+        let storage = Storage::new(temp_db_path("defaults")).expect("storage should initialize");
+
+        assert_eq!(storage.get_theme().await.unwrap(), "system");
+        assert_eq!(storage.get_refresh_time().await.unwrap(), 300);
+        assert!(storage.get_show_notification().await.unwrap());
+        assert_eq!(storage.get_reminder_hours().await.unwrap(), None);
+        assert!(!storage.get_dry_run().await.unwrap());
+        assert_eq!(
+            storage.get_default_mode().await.unwrap(),
+            MonitorMode::AutoUpdate
+        );
+    }
+
+    #[tokio::test]
+    async fn duplicate_pull_requests_are_scoped_by_repository() {
+        let storage = Storage::new(temp_db_path("identity")).expect("storage should initialize");
+
+        storage
+            .add_pull_request(pull_request("owner-a", "repo", 7))
+            .await
+            .unwrap();
+        storage
+            .add_pull_request(pull_request("owner-b", "repo", 7))
+            .await
+            .unwrap();
+
+        let duplicate = storage
+            .add_pull_request(pull_request("owner-a", "repo", 7))
+            .await
+            .unwrap_err();
+
+        assert!(matches!(duplicate, StorageError::PullRequestAlreadyExists));
+    }
+
+    #[tokio::test]
+    async fn mode_of_an_unwatched_pull_request_is_not_set() {
+        let storage = Storage::new(temp_db_path("mode")).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+
+        storage
+            .set_pull_request_mode(PullRequestKey::new("owner", "repo", 7), MonitorMode::Silent)
+            .await
+            .unwrap();
+        let unwatched = storage
+            .set_pull_request_mode(PullRequestKey::new("owner", "repo", 8), MonitorMode::Silent)
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            storage.get_all_pull_requests().await.unwrap()[0].mode,
+            MonitorMode::Silent
+        );
+        assert!(matches!(unwatched, StorageError::PullRequestNotFound));
+    }
+
+    #[tokio::test]
+    async fn update_method_of_an_unwatched_pull_request_is_not_set() {
+        let storage = Storage::new(temp_db_path("unwatched-update-method"))
+            .expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+
+        let unwatched = storage
+            .set_pull_request_update_method(
+                PullRequestKey::new("owner", "repo", 8),
+                Some(UpdateMethod::Rebase),
+            )
+            .await
+            .unwrap_err();
+
+        assert!(matches!(unwatched, StorageError::PullRequestNotFound));
+        assert_eq!(
+            storage.get_all_pull_requests().await.unwrap()[0].update_method,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn delete_pull_request_uses_repository_identity() {
+        let storage = Storage::new(temp_db_path("delete")).expect("storage should initialize");
+
+        storage
+            .add_pull_request(pull_request("owner-a", "repo", 7))
+            .await
+            .unwrap();
+        storage
+            .add_pull_request(pull_request("owner-b", "repo", 7))
+            .await
+            .unwrap();
+
+        storage
+            .delete_pull_request(PullRequestKey::new("owner-a", "repo", 7))
             .await
             .unwrap();
 
@@ -334,4 +1376,475 @@ mod tests {
 
         assert_eq!(remaining, vec![pull_request("owner-b", "repo", 7)]);
     }
+
+    #[tokio::test]
+    async fn pull_request_state_follows_transitions_both_ways() {
+        let storage = Storage::new(temp_db_path("state")).expect("storage should initialize");
+        for pr_number in [1, 2, 3] {
+            storage
+                .add_pull_request(pull_request("owner", "repo", pr_number))
+                .await
+                .unwrap();
+        }
+        let numbers = |prs: Vec<PullRequestModel>| {
+            prs.iter()
+                .map(|pr| (pr.pr_number, pr.state))
+                .collect::<Vec<_>>()
+        };
+
+        storage
+            .set_pull_request_state(
+                PullRequestKey::new("owner", "repo", 1),
+                PullRequestState::Merged,
+                added_at(),
+            )
+            .await
+            .unwrap();
+        storage
+            .set_pull_request_state(
+                PullRequestKey::new("owner", "repo", 2),
+                PullRequestState::Closed,
+                added_at(),
+            )
+            .await
+            .unwrap();
+        let unmerged = storage
+            .get_unmerged_pull_requests(added_at())
+            .await
+            .unwrap();
+        let recently_closed = storage
+            .get_unmerged_pull_requests(added_at() + chrono::Duration::days(1))
+            .await
+            .unwrap();
+        storage
+            .set_pull_request_state(
+                PullRequestKey::new("owner", "repo", 2),
+                PullRequestState::Open,
+                added_at(),
+            )
+            .await
+            .unwrap();
+        let open = storage.get_open_pull_requests().await.unwrap();
+
+        assert_eq!(
+            numbers(unmerged),
+            vec![(2, PullRequestState::Closed), (3, PullRequestState::Open)]
+        );
+        assert_eq!(numbers(recently_closed), vec![(3, PullRequestState::Open)]);
+        assert_eq!(
+            numbers(open),
+            vec![(2, PullRequestState::Open), (3, PullRequestState::Open)]
+        );
+        assert_eq!(storage.get_all_pull_requests().await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn last_error_is_stored_and_cleared_per_pull_request() {
+        let storage = Storage::new(temp_db_path("last-error")).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+        let key = PullRequestKey::new("owner", "repo", 7);
+
+        storage
+            .set_last_error(key.clone(), Some("Not Found".to_owned()))
+            .await
+            .unwrap();
+        let failed = storage.get_open_pull_requests().await.unwrap();
+        storage.set_last_error(key, None).await.unwrap();
+        let cleared = storage.get_open_pull_requests().await.unwrap();
+
+        assert_eq!(failed[0].last_error.as_deref(), Some("Not Found"));
+        assert_eq!(cleared[0].last_error, None);
+    }
+
+    #[tokio::test]
+    async fn update_settings_are_stored_per_pull_request_and_repository() {
+        let storage =
+            Storage::new(temp_db_path("update-method")).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+        let key = PullRequestKey::new("owner", "repo", 7);
+
+        storage
+            .set_pull_request_update_method(key.clone(), Some(UpdateMethod::Rebase))
+            .await
+            .unwrap();
+        storage
+            .set_pull_request_auto_merge(key.clone(), Some(MergeMethod::Squash))
+            .await
+            .unwrap();
+        storage
+            .set_auto_merge_enabled(key.clone(), true)
+            .await
+            .unwrap();
+        storage
+            .set_repository_update_method("owner".into(), "repo".into(), Some(UpdateMethod::Rebase))
+            .await
+            .unwrap();
+        storage
+            .set_repository_update_method("owner".into(), "other".into(), Some(UpdateMethod::Merge))
+            .await
+            .unwrap();
+        storage
+            .set_repository_update_method("owner".into(), "other".into(), None)
+            .await
+            .unwrap();
+
+        storage
+            .set_repository_behind_threshold("owner".into(), "repo".into(), Some(3))
+            .await
+            .unwrap();
+        storage
+            .set_repository_behind_threshold("owner".into(), "third".into(), Some(5))
+            .await
+            .unwrap();
+
+        let pull_requests = storage.get_open_pull_requests().await.unwrap();
+        assert_eq!(pull_requests[0].update_method, Some(UpdateMethod::Rebase));
+        assert_eq!(pull_requests[0].auto_merge, Some(MergeMethod::Squash));
+        assert!(pull_requests[0].auto_merge_enabled);
+        assert_eq!(
+            storage.get_repository_behind_thresholds().await.unwrap(),
+            HashMap::from([
+                (("owner".to_owned(), "repo".to_owned()), 3),
+                (("owner".to_owned(), "third".to_owned()), 5),
+            ])
+        );
+        assert_eq!(
+            storage.get_repository_update_methods().await.unwrap(),
+            HashMap::from([(
+                ("owner".to_owned(), "repo".to_owned()),
+                UpdateMethod::Rebase
+            )])
+        );
+    }
+
+    #[tokio::test]
+    async fn cached_responses_are_replaced_and_dropped_with_the_pull_request() {
+        let storage = Storage::new(temp_db_path("etag")).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+        let key = PullRequestKey::new("owner", "repo", 7);
+        let cached = |etag: &str, status| CachedPullRequest {
+            etag: etag.to_owned(),
+            snapshot: PullRequestSnapshot {
+                title: "Title".to_owned(),
+                state: PullRequestState::Open,
+                status,
+                head_sha: "abc123".to_owned(),
+                base_ref: "main".to_owned(),
+                auto_merge_enabled: None,
+                merge_queue: None,
+                approved: None,
+            },
+        };
+
+        storage
+            .set_cached_pull_request(key.clone(), cached("\"a\"", PrStatus::Behind))
+            .await
+            .unwrap();
+        storage
+            .set_cached_pull_request(key.clone(), cached("\"b\"", PrStatus::UpToDate))
+            .await
+            .unwrap();
+        let stored = storage.get_cached_pull_request(key.clone()).await.unwrap();
+        storage.delete_pull_request(key.clone()).await.unwrap();
+        let deleted = storage.get_cached_pull_request(key).await.unwrap();
+
+        assert_eq!(stored, Some(cached("\"b\"", PrStatus::UpToDate)));
+        assert_eq!(deleted, None);
+    }
+
+    #[tokio::test]
+    async fn status_changed_at_only_moves_when_the_status_changes() {
+        let storage = Storage::new(temp_db_path("status")).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+        let key = PullRequestKey::new("owner", "repo", 7);
+        let at = |minutes| added_at() + chrono::Duration::minutes(minutes);
+
+        for (minutes, status) in [
+            (1, PrStatus::Behind),
+            (2, PrStatus::Behind),
+            (3, PrStatus::UpToDate),
+        ] {
+            storage
+                .record_status(key.clone(), status, at(minutes))
+                .await
+                .unwrap();
+            if minutes == 2 {
+                let pr = &storage.get_open_pull_requests().await.unwrap()[0];
+                assert_eq!(pr.status_changed_at, Some(at(1)));
+                assert_eq!(pr.last_checked_at, Some(at(2)));
+            }
+        }
+        storage
+            .set_pull_request_state(key, PullRequestState::Merged, at(4))
+            .await
+            .unwrap();
+
+        let pr = &storage.get_all_pull_requests().await.unwrap()[0];
+        assert_eq!(pr.last_status, Some(PrStatus::UpToDate));
+        assert_eq!(pr.status_changed_at, Some(at(3)));
+        assert_eq!(pr.last_checked_at, Some(at(3)));
+        assert_eq!(pr.added_at, Some(added_at()));
+        assert_eq!(pr.merged_at, Some(at(4)));
+        assert_eq!(pr.closed_at, Some(at(4)));
+    }
+
+    #[tokio::test]
+    async fn legacy_closed_at_text_is_migrated_to_rfc3339() {
+        let db_path = temp_db_path("legacy-closed-at");
+        {
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE pull_request (
+                    id INTEGER PRIMARY KEY,
+                    owner TEXT NOT NULL,
+                    repo TEXT NOT NULL,
+                    pr_number INTEGER NOT NULL,
+                    title TEXT NOT NULL,
+                    state TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    closed_at TEXT
+                );
+                INSERT INTO pull_request (owner, repo, pr_number, title, state, url, closed_at)
+                VALUES ('owner', 'repo', 1, 'One', 'closed', 'url', '14/11/2023 22:13'),
+                       ('owner', 'repo', 2, 'Two', 'closed', 'url', 'yesterday');",
+            )
+            .unwrap();
+        }
+
+        let storage = Storage::new(db_path).expect("storage should migrate");
+        let prs = storage.get_all_pull_requests().await.unwrap();
+
+        assert_eq!(
+            prs[0].closed_at,
+            Some(DateTime::from_timestamp(1_700_000_000 - 20, 0).unwrap())
+        );
+        assert_eq!(prs[1].closed_at, None);
+        assert_eq!(prs[0].added_at, None);
+    }
+
+    #[tokio::test]
+    async fn legacy_closed_pull_requests_stay_closed_until_checked() {
+        let db_path = temp_db_path("legacy-closed-state");
+        {
+            let conn = Connection::open(&db_path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE pull_request (
+                    id INTEGER PRIMARY KEY,
+                    owner TEXT NOT NULL,
+                    repo TEXT NOT NULL,
+                    pr_number INTEGER NOT NULL,
+                    title TEXT NOT NULL,
+                    state TEXT NOT NULL,
+                    url TEXT NOT NULL,
+                    closed_at TEXT
+                );
+                INSERT INTO pull_request (owner, repo, pr_number, title, state, url, closed_at)
+                VALUES ('owner', 'repo', 1, 'Abandoned', 'closed', 'url', '14/11/2023 22:13'),
+                       ('owner', 'repo', 2, 'Merged', 'closed', 'url', '14/11/2023 22:13');",
+            )
+            .unwrap();
+        }
+
+        let storage = Storage::new(db_path).expect("storage should migrate");
+        let prs = storage.get_all_pull_requests().await.unwrap();
+        let unchecked = storage
+            .get_unmerged_pull_requests(Utc::now())
+            .await
+            .unwrap();
+        storage
+            .record_status(
+                PullRequestKey::new("owner", "repo", 1),
+                PrStatus::Closed,
+                added_at(),
+            )
+            .await
+            .unwrap();
+        let checked = storage
+            .get_unmerged_pull_requests(Utc::now())
+            .await
+            .unwrap();
+
+        assert!(prs
+            .iter()
+            .all(|pr| pr.state == PullRequestState::Closed && pr.merged_at.is_none()));
+        assert_eq!(unchecked.len(), 2);
+        assert_eq!(
+            checked.iter().map(|pr| pr.pr_number).collect::<Vec<_>>(),
+            vec![2]
+        );
+    }
+
+    /// Each schema the app has shipped before `user_version` was set, oldest first.
+    /// Every entry applies on top of the ones before it.
+    const HISTORICAL_SCHEMAS: &[(&str, &str)] = &[
+        (
+            "pre_unique_index",
+            "CREATE TABLE pull_request (
+                id INTEGER PRIMARY KEY,
+                owner TEXT NOT NULL,
+                repo TEXT NOT NULL,
+                pr_number INTEGER NOT NULL,
+                title TEXT NOT NULL,
+                state TEXT NOT NULL,
+                url TEXT NOT NULL,
+                closed_at TEXT
+            );
+            CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+            CREATE TABLE token (id INTEGER PRIMARY KEY, token TEXT NOT NULL);",
+        ),
+        (
+            "unique_index",
+            "DROP TABLE token;
+            CREATE UNIQUE INDEX idx_pull_request_identity
+            ON pull_request(owner, repo, pr_number);",
+        ),
+    ];
+
+    fn user_version(conn: &Connection) -> u32 {
+        conn.pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn every_historical_schema_upgrades_to_the_latest_version() {
+        for count in 1..=HISTORICAL_SCHEMAS.len() {
+            let (name, _) = HISTORICAL_SCHEMAS[count - 1];
+            let db_path = temp_db_path(&format!("schema-{name}"));
+            {
+                let conn = Connection::open(&db_path).unwrap();
+                for (_, sql) in &HISTORICAL_SCHEMAS[..count] {
+                    conn.execute_batch(sql).unwrap();
+                }
+                conn.execute(
+                    "INSERT INTO pull_request (owner, repo, pr_number, title, state, url, closed_at)
+                     VALUES ('acme', 'widgets', 7, 'Title', 'closed', 'url', '14/11/2023 22:13')",
+                    [],
+                )
+                .unwrap();
+                if name == "pre_unique_index" {
+                    conn.execute(
+                        "INSERT INTO pull_request (owner, repo, pr_number, title, state, url)
+                         VALUES ('acme', 'widgets', 7, 'Duplicate', 'open', 'url')",
+                        [],
+                    )
+                    .unwrap();
+                }
+            }
+
+            let storage = Storage::new(db_path.clone())
+                .unwrap_or_else(|err| panic!("{name} should migrate: {err}"));
+            let prs = storage.get_all_pull_requests().await.unwrap();
+            let key = PullRequestKey::new("acme", "widgets", 7);
+            let cached = storage.get_cached_pull_request(key.clone()).await;
+            storage
+                .set_last_notified_at(key, added_at())
+                .await
+                .unwrap_or_else(|err| panic!("{name} should track notifications: {err}"));
+            drop(storage);
+
+            let conn = Connection::open(&db_path).unwrap();
+            assert_eq!(user_version(&conn), MIGRATIONS.len() as u32, "{name}");
+            assert_eq!(prs.len(), 1, "{name}");
+            assert_eq!(prs[0].title, "Title", "{name}");
+            assert_eq!(
+                prs[0].closed_at,
+                Some(DateTime::from_timestamp(1_700_000_000 - 20, 0).unwrap()),
+                "{name}"
+            );
+            assert_eq!(prs[0].state, PullRequestState::Closed, "{name}");
+            assert_eq!(prs[0].merged_at, None, "{name}");
+            assert!(matches!(cached, Ok(None)), "{name}");
+            let token_tables: u32 = conn
+                .query_row(
+                    "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'token'",
+                    [],
+                    |row| row.get(0),
+                )
+                .unwrap();
+            assert_eq!(token_tables, 0, "{name}");
+        }
+    }
+
+    #[tokio::test]
+    async fn migrations_run_once_per_database() {
+        let db_path = temp_db_path("migrate-once");
+        let storage = Storage::new(db_path.clone()).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("acme", "widgets", 7))
+            .await
+            .unwrap();
+        drop(storage);
+
+        let reopened = Storage::new(db_path.clone()).expect("storage should reopen");
+
+        assert_eq!(
+            reopened.get_all_pull_requests().await.unwrap(),
+            vec![pull_request("acme", "widgets", 7)]
+        );
+        assert_eq!(
+            user_version(&Connection::open(&db_path).unwrap()),
+            MIGRATIONS.len() as u32
+        );
+    }
+
+    #[test]
+    fn databases_from_a_newer_app_are_refused() {
+        let db_path = temp_db_path("newer-schema");
+        Connection::open(&db_path)
+            .unwrap()
+            .pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1)
+            .unwrap();
+
+        let err = Storage::new(db_path)
+            .err()
+            .expect("newer schema should fail");
+
+        assert!(matches!(
+            err,
+            StorageError::UnsupportedSchemaVersion { found, supported }
+                if found == supported + 1
+        ));
+    }
+
+    #[tokio::test]
+    async fn events_are_listed_in_time_order_and_dropped_with_the_pull_request() {
+        let storage = Storage::new(temp_db_path("events")).expect("storage should initialize");
+        storage
+            .add_pull_request(pull_request("owner", "repo", 7))
+            .await
+            .unwrap();
+        let key = PullRequestKey::new("owner", "repo", 7);
+        let at = |minutes| added_at() + chrono::Duration::minutes(minutes);
+        let blocked = PullRequestEvent {
+            status: Some(PrStatus::Blocked),
+            ..PullRequestEvent::new(PullRequestEventKind::StatusChanged, at(1))
+        };
+        let failed = PullRequestEvent {
+            detail: Some("Not Found".to_owned()),
+            ..PullRequestEvent::new(PullRequestEventKind::BranchUpdateFailed, at(2))
+        };
+        let closed = PullRequestEvent::new(PullRequestEventKind::Closed, at(3));
+
+        for event in [failed.clone(), blocked.clone(), closed.clone()] {
+            storage.record_event(key.clone(), event).await.unwrap();
+        }
+        let history = storage.get_pull_request_events(key.clone()).await.unwrap();
+        storage.delete_pull_request(key.clone()).await.unwrap();
+        let deleted = storage.get_pull_request_events(key).await.unwrap();
+
+        assert_eq!(history, vec![blocked, failed, closed]);
+        assert!(deleted.is_empty());
+    }
 }
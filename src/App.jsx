@@ -12,7 +12,26 @@ function App() {
   const [prUrl, setPrUrl] = useState("");
   const [refreshTime, setRefreshTime] = useState(5);
   const [showNotification, setShowNotification] = useState(true);
+  const [autoEnqueue, setAutoEnqueue] = useState(false);
+  const [maxConcurrentChecks, setMaxConcurrentChecks] = useState(4);
+  const [reminderHours, setReminderHours] = useState(0);
+  const [defaultMode, setDefaultMode] = useState("auto_update");
+  const [ciPolicy, setCiPolicy] = useState("always");
+  const [behindThreshold, setBehindThreshold] = useState(1);
+  const [repoBehindThresholds, setRepoBehindThresholds] = useState([]);
+  const [thresholdRepoInput, setThresholdRepoInput] = useState("");
+  const [thresholdCommitsInput, setThresholdCommitsInput] = useState(1);
+  const [maxUpdatesPerHour, setMaxUpdatesPerHour] = useState(0);
+  const [updateSpacingMinutes, setUpdateSpacingMinutes] = useState(0);
+  const [rerunBudget, setRerunBudget] = useState(0);
+  const [rerunJobPatterns, setRerunJobPatterns] = useState("");
+  const [repoUpdateMethods, setRepoUpdateMethods] = useState([]);
+  const [repoInput, setRepoInput] = useState("");
+  const [repoMethodInput, setRepoMethodInput] = useState("rebase");
   const [theme, setTheme] = useState("system");
+  const [rateLimit, setRateLimit] = useState(null);
+  const [history, setHistory] = useState(null);
+  const [auditExportPath, setAuditExportPath] = useState("");
 
   useEffect(() => {
     const applyTheme = async () => {
@@ -67,6 +86,189 @@ function App() {
 
   const prKey = (pr) => `${pr.owner}/${pr.repo}#${pr.pr_number}`;
 
+  const statusLabels = {
+    behind: "Behind",
+    up_to_date: "Up to date",
+    conflicts: "Has conflicts",
+    blocked: "Blocked",
+    queued: "In merge queue",
+    pending: "Checking",
+    unknown: "Unknown status",
+    merged: "Merged",
+    closed: "Closed",
+  };
+
+  const timeSince = (timestamp) => {
+    const minutes = Math.floor((Date.now() - new Date(timestamp).getTime()) / 60000);
+    if (minutes < 1) return "just now";
+    if (minutes < 60) return `${minutes}m`;
+    if (minutes < 60 * 24) return `${Math.floor(minutes / 60)}h`;
+    return `${Math.floor(minutes / (60 * 24))}d`;
+  };
+
+  const modeLabels = {
+    auto_update: "Auto-update",
+    notify_only: "Notify only",
+    silent: "Silent",
+  };
+
+  async function changeMode(pr, mode) {
+    try {
+      await invoke("set_pr_mode", {
+        owner: pr.owner,
+        repo: pr.repo,
+        prNumber: pr.pr_number,
+        mode,
+      });
+      setPrList((currentList) =>
+        currentList.map((item) => (prKey(item) === prKey(pr) ? { ...item, mode } : item))
+      );
+    } catch (error) {
+      updateErrorMessage(error);
+    }
+  }
+
+  const ciPolicyLabels = {
+    always: "Always",
+    not_while_pending: "Not while CI is running",
+    only_when_passing: "Only when CI passed",
+  };
+
+  const updateMethodLabels = {
+    merge: "Merge",
+    rebase: "Rebase",
+  };
+
+  async function changeUpdateMethod(pr, value) {
+    const method = value || null;
+    try {
+      await invoke("set_pr_update_method", {
+        owner: pr.owner,
+        repo: pr.repo,
+        prNumber: pr.pr_number,
+        method,
+      });
+      setPrList((currentList) =>
+        currentList.map((item) =>
+          prKey(item) === prKey(pr) ? { ...item, update_method: method } : item
+        )
+      );
+    } catch (error) {
+      updateErrorMessage(error);
+    }
+  }
+
+  const mergeMethodLabels = {
+    merge: "Merge",
+    squash: "Squash",
+    rebase: "Rebase",
+  };
+
+  async function changeAutoMerge(pr, value) {
+    const method = value || null;
+    try {
+      await invoke("set_pr_auto_merge", {
+        owner: pr.owner,
+        repo: pr.repo,
+        prNumber: pr.pr_number,
+        method,
+      });
+      setPrList((currentList) =>
+        currentList.map((item) =>
+          prKey(item) === prKey(pr) ? { ...item, auto_merge: method } : item
+        )
+      );
+    } catch (error) {
+      updateErrorMessage(error);
+    }
+  }
+
+  async function saveRepoUpdateMethod(fullName, method) {
+    const [owner, repo] = fullName.trim().split("/");
+    if (!owner || !repo) {
+      updateErrorMessage("Enter the repository as owner/repo");
+      return;
+    }
+    try {
+      await invoke("set_repo_update_method", { owner, repo, method });
+      setRepoUpdateMethods(await invoke("get_repo_update_methods"));
+      setRepoInput("");
+    } catch (error) {
+      updateErrorMessage(error);
+    }
+  }
+
+  async function saveRepoBehindThreshold(fullName, commits) {
+    const [owner, repo] = fullName.trim().split("/");
+    if (!owner || !repo) {
+      updateErrorMessage("Enter the repository as owner/repo");
+      return;
+    }
+    try {
+      await invoke("set_repo_behind_threshold", { owner, repo, commits });
+      setRepoBehindThresholds(await invoke("get_repo_behind_thresholds"));
+      setThresholdRepoInput("");
+    } catch (error) {
+      updateErrorMessage(error);
+    }
+  }
+
+  async function toggleHistory(pr) {
+    if (history && history.key === prKey(pr)) {
+      setHistory(null);
+      return;
+    }
+    try {
+      const events = await invoke("get_pr_history", {
+        owner: pr.owner,
+        repo: pr.repo,
+        prNumber: pr.pr_number,
+      });
+      setHistory({ key: prKey(pr), events });
+    } catch (error) {
+      updateErrorMessage(error);
+    }
+  }
+
+  const eventLabel = (event) => {
+    switch (event.kind) {
+      case "status_changed":
+        return statusLabels[event.status] ?? event.status;
+      case "branch_updated":
+        return "Branch updated";
+      case "branch_update_failed":
+        return `Branch update failed: ${event.detail}`;
+      case "merged":
+        return "Merged";
+      case "closed":
+        return "Closed";
+      case "reopened":
+        return "Reopened";
+      case "jobs_rerun":
+        return `Re-ran ${event.detail}`;
+      case "reruns_exhausted":
+        return `No re-runs left, still failing: ${event.detail}`;
+      default:
+        return event.kind;
+    }
+  };
+
+  const renderHistory = (pr) =>
+    history &&
+    history.key === prKey(pr) && (
+      <ol className="mt-1 text-xs text-gray-500 dark:text-gray-400">
+        {history.events.length > 0 ? (
+          history.events.map((event, index) => (
+            <li key={index} className="truncate" title={eventLabel(event)}>
+              {new Date(event.at).toLocaleString()} · {eventLabel(event)}
+            </li>
+          ))
+        ) : (
+          <li>No history yet</li>
+        )}
+      </ol>
+    );
+
   async function deletePr(pr) {
     try {
       await invoke("delete_pr", {
@@ -97,27 +299,96 @@ function App() {
       invoke("get_show_notification")
         .then(setShowNotification)
         .catch(console.error);
+      invoke("get_auto_enqueue").then(setAutoEnqueue).catch(console.error);
+      invoke("get_max_concurrent_checks")
+        .then(setMaxConcurrentChecks)
+        .catch(console.error);
+      invoke("get_default_mode").then(setDefaultMode).catch(console.error);
+      invoke("get_ci_policy").then(setCiPolicy).catch(console.error);
+      invoke("get_behind_threshold").then(setBehindThreshold).catch(console.error);
+      invoke("get_repo_behind_thresholds").then(setRepoBehindThresholds).catch(console.error);
+      invoke("get_max_updates_per_hour")
+        .then((limit) => setMaxUpdatesPerHour(limit ?? 0))
+        .catch(console.error);
+      invoke("get_update_spacing_minutes")
+        .then((minutes) => setUpdateSpacingMinutes(minutes ?? 0))
+        .catch(console.error);
+      invoke("get_rerun_budget")
+        .then((budget) => setRerunBudget(budget ?? 0))
+        .catch(console.error);
+      invoke("get_rerun_job_patterns")
+        .then((patterns) => setRerunJobPatterns(patterns.join(", ")))
+        .catch(console.error);
+      invoke("get_repo_update_methods").then(setRepoUpdateMethods).catch(console.error);
+      invoke("get_reminder_hours")
+        .then((hours) => setReminderHours(hours ?? 0))
+        .catch(console.error);
     }
     initializeState();
 
     const unlistenError = listen("error-event", (event) => {
       updateErrorMessage(event.payload);
     });
+    const setPrState = (changedPr, state) =>
+      setPrList((currentList) =>
+        currentList.map((pr) =>
+          prKey(pr) === prKey(changedPr) ? { ...pr, state } : pr
+        )
+      );
+    const unlistenPrMerged = listen("pr-merged", (event) => {
+      setPrState(event.payload, "merged");
+    });
     const unlistenPrClosed = listen("pr-closed", (event) => {
-      const closedPr = event.payload;
+      setPrState(event.payload, "closed");
+    });
+    const unlistenPrReopened = listen("pr-reopened", (event) => {
+      setPrState(event.payload, "open");
+    });
+
+    const unlistenPrError = listen("pr-error", (event) => {
+      const failedPr = event.payload;
+      setPrList((currentList) =>
+        currentList.map((pr) =>
+          prKey(pr) === prKey(failedPr) ? { ...pr, last_error: failedPr.error } : pr
+        )
+      );
+    });
+    const unlistenPrStatus = listen("pr-status", (event) => {
+      const checkedPr = event.payload;
       setPrList((currentList) =>
         currentList.map((pr) =>
-          prKey(pr) === prKey(closedPr) ? { ...pr, state: "closed" } : pr
+          prKey(pr) === prKey(checkedPr)
+            ? {
+                ...pr,
+                last_error: null,
+                last_status: checkedPr.status,
+                last_checked_at: new Date().toISOString(),
+                status_changed_at:
+                  pr.last_status === checkedPr.status
+                    ? pr.status_changed_at
+                    : new Date().toISOString(),
+              }
+            : pr
         )
       );
     });
 
     return () => {
       unlistenError.then((fn) => fn());
+      unlistenPrMerged.then((fn) => fn());
       unlistenPrClosed.then((fn) => fn());
+      unlistenPrReopened.then((fn) => fn());
+      unlistenPrError.then((fn) => fn());
+      unlistenPrStatus.then((fn) => fn());
     };
   }, []);
 
+  useEffect(() => {
+    if (activeTab === "settings") {
+      invoke("get_rate_limit").then(setRateLimit).catch(console.error);
+    }
+  }, [activeTab]);
+
   useEffect(() => {
     invoke("start_task").catch(console.error);
     return () => {
@@ -171,7 +442,7 @@ function App() {
   const inactiveTabStyle = "border-transparent hover:text-gray-600 hover:border-gray-300 dark:hover:text-gray-300";
 
   const prListOpen = prList.filter((pr) => pr.state === "open");
-  const prListClosed = prList.filter((pr) => pr.state === "closed");
+  const prListClosed = prList.filter((pr) => pr.state === "closed" || pr.state === "merged");
 
   return (
     <main className="bg-white dark:bg-gray-900 text-black dark:text-white min-h-screen rounded-lg overflow-hidden">
@@ -218,7 +489,56 @@ function App() {
                     <a href={buildUrlFromPr(pr)} target="_blank" rel="noopener noreferrer" className="hover:underline">
                       {pr.title}
                     </a>
+                    {statusLabels[pr.last_status] && (
+                      <p className="text-xs text-gray-500 dark:text-gray-400" title={pr.last_checked_at && `Last checked ${timeSince(pr.last_checked_at)} ago`}>
+                        {statusLabels[pr.last_status]}
+                        {pr.last_status === "behind" && pr.behind_by > 0 && ` by ${pr.behind_by}`}
+                        {pr.status_changed_at && ` since ${timeSince(pr.status_changed_at)}`}
+                        {pr.auto_merge_enabled && " · auto-merge on"}
+                      </p>
+                    )}
+                    {pr.last_error && (
+                      <p className="text-xs text-red-600 dark:text-red-400 truncate" title={pr.last_error}>
+                        {pr.last_error}
+                      </p>
+                    )}
+                    {renderHistory(pr)}
                   </div>
+                  <select
+                    value={pr.mode}
+                    onChange={(e) => changeMode(pr, e.target.value)}
+                    className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none text-xs ml-2 flex-shrink-0"
+                    title="Monitoring mode"
+                  >
+                    {Object.entries(modeLabels).map(([mode, label]) => (
+                      <option key={mode} value={mode}>{label}</option>
+                    ))}
+                  </select>
+                  <select
+                    value={pr.update_method ?? ""}
+                    onChange={(e) => changeUpdateMethod(pr, e.target.value)}
+                    className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none text-xs ml-2 flex-shrink-0"
+                    title="Update method"
+                  >
+                    <option value="">Repo default</option>
+                    {Object.entries(updateMethodLabels).map(([method, label]) => (
+                      <option key={method} value={method}>{label}</option>
+                    ))}
+                  </select>
+                  <select
+                    value={pr.auto_merge ?? ""}
+                    onChange={(e) => changeAutoMerge(pr, e.target.value)}
+                    className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none text-xs ml-2 flex-shrink-0"
+                    title="Auto-merge"
+                  >
+                    <option value="">No auto-merge</option>
+                    {Object.entries(mergeMethodLabels).map(([method, label]) => (
+                      <option key={method} value={method}>Auto-{label.toLowerCase()}</option>
+                    ))}
+                  </select>
+                  <button onClick={() => toggleHistory(pr)} className="text-gray-500 hover:text-gray-700 dark:hover:text-gray-300 p-1 ml-2 flex-shrink-0" title="History">
+                    🕘
+                  </button>
                   <button onClick={() => deletePr(pr)} className="text-red-500 hover:text-red-700 font-bold p-1 ml-2 flex-shrink-0">
                     &times;
                   </button>
@@ -243,7 +563,14 @@ function App() {
                     <a href={buildUrlFromPr(pr)} target="_blank" rel="noopener noreferrer" className="hover:underline">
                       {pr.title}
                     </a>
+                    <p className="text-xs text-gray-500 dark:text-gray-400">
+                      {pr.state === "merged" ? "Merged" : "Closed without merging"}
+                    </p>
+                    {renderHistory(pr)}
                   </div>
+                  <button onClick={() => toggleHistory(pr)} className="text-gray-500 hover:text-gray-700 dark:hover:text-gray-300 p-1 ml-2 flex-shrink-0" title="History">
+                    🕘
+                  </button>
                   <button onClick={() => deletePr(pr)} className="text-red-500 hover:text-red-700 font-bold p-1 ml-2 flex-shrink-0">
                     &times;
                   </button>
@@ -289,6 +616,20 @@ function App() {
               Save
             </button>
           </form>
+          <form className="flex items-center justify-start gap-4" onSubmit={(e) => { e.preventDefault(); invoke("set_max_concurrent_checks", { limit: Number(maxConcurrentChecks) }).catch(updateErrorMessage); }}>
+            <label htmlFor="max-concurrent-checks-input" className="text-gray-600 dark:text-gray-300">Parallel checks:</label>
+            <input
+              id="max-concurrent-checks-input"
+              type="number"
+              min="1"
+              value={maxConcurrentChecks}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-20 text-center"
+              onChange={(e) => setMaxConcurrentChecks(e.currentTarget.value)}
+            />
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
           <div className="flex items-center justify-start gap-2">
             <label htmlFor="show-notification-input" className="text-gray-600 dark:text-gray-300">Show notifications:</label>
             <button
@@ -308,6 +649,230 @@ function App() {
               />
             </button>
           </div>
+          <div className="flex items-center justify-start gap-2">
+            <label htmlFor="auto-enqueue-input" className="text-gray-600 dark:text-gray-300" title="Adds approved PRs with passing CI to their merge queue">Add to merge queue:</label>
+            <button
+              id="auto-enqueue-input"
+              role="switch"
+              aria-checked={autoEnqueue}
+              onClick={() => {
+                const newAutoEnqueue = !autoEnqueue;
+                setAutoEnqueue(newAutoEnqueue);
+                invoke("set_auto_enqueue", { enabled: newAutoEnqueue }).catch(updateErrorMessage);
+              }}
+              className={`${autoEnqueue ? "bg-blue-500" : "bg-gray-200 dark:bg-gray-700"} relative inline-flex h-6 w-11 flex-shrink-0 cursor-pointer rounded-full border-2 border-transparent transition-colors duration-200 ease-in-out focus:outline-none`}
+            >
+              <span
+                aria-hidden="true"
+                className={`${autoEnqueue ? "translate-x-5" : "translate-x-0"} pointer-events-none inline-block h-5 w-5 transform rounded-full bg-white shadow ring-0 transition duration-200 ease-in-out`}
+              />
+            </button>
+          </div>
+          <div className="flex items-center justify-start gap-4">
+            <label htmlFor="default-mode-input" className="text-gray-600 dark:text-gray-300">Mode for new PRs:</label>
+            <select
+              id="default-mode-input"
+              value={defaultMode}
+              onChange={(e) => {
+                setDefaultMode(e.target.value);
+                invoke("set_default_mode", { mode: e.target.value }).catch(updateErrorMessage);
+              }}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none py-1 px-2"
+            >
+              {Object.entries(modeLabels).map(([mode, label]) => (
+                <option key={mode} value={mode}>{label}</option>
+              ))}
+            </select>
+          </div>
+          <form className="flex items-center justify-start gap-4" onSubmit={(e) => { e.preventDefault(); invoke("set_behind_threshold", { commits: Number(behindThreshold) || 1 }).catch(updateErrorMessage); }}>
+            <label htmlFor="behind-threshold-input" className="text-gray-600 dark:text-gray-300">Update once behind by (commits):</label>
+            <input
+              id="behind-threshold-input"
+              type="number"
+              min="1"
+              value={behindThreshold}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-20 text-center"
+              onChange={(e) => setBehindThreshold(e.currentTarget.value)}
+            />
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
+          <form className="flex items-center justify-start gap-4" onSubmit={(e) => { e.preventDefault(); saveRepoBehindThreshold(thresholdRepoInput, Number(thresholdCommitsInput) || null); }}>
+            <label htmlFor="repo-behind-threshold-input" className="text-gray-600 dark:text-gray-300">Repository threshold:</label>
+            <input
+              id="repo-behind-threshold-input"
+              placeholder="owner/repo"
+              value={thresholdRepoInput}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-40 px-2"
+              onChange={(e) => setThresholdRepoInput(e.currentTarget.value)}
+            />
+            <input
+              type="number"
+              min="1"
+              value={thresholdCommitsInput}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-16 text-center"
+              onChange={(e) => setThresholdCommitsInput(e.currentTarget.value)}
+            />
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
+          {repoBehindThresholds.map(({ owner, repo, commits }) => (
+            <div key={`${owner}/${repo}`} className="flex items-center justify-start gap-2 text-sm text-gray-600 dark:text-gray-300">
+              <span>{owner}/{repo}: {commits} commit(s)</span>
+              <button onClick={() => saveRepoBehindThreshold(`${owner}/${repo}`, null)} className="text-red-500 hover:text-red-700 font-bold" title="Back to the global threshold">
+                &times;
+              </button>
+            </div>
+          ))}
+          <div className="flex items-center justify-start gap-4">
+            <label htmlFor="ci-policy-input" className="text-gray-600 dark:text-gray-300">Update behind branches:</label>
+            <select
+              id="ci-policy-input"
+              value={ciPolicy}
+              onChange={(e) => {
+                setCiPolicy(e.target.value);
+                invoke("set_ci_policy", { policy: e.target.value }).catch(updateErrorMessage);
+              }}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none py-1 px-2"
+            >
+              {Object.entries(ciPolicyLabels).map(([policy, label]) => (
+                <option key={policy} value={policy}>{label}</option>
+              ))}
+            </select>
+          </div>
+          <form
+            className="flex items-center justify-start gap-4"
+            onSubmit={(e) => {
+              e.preventDefault();
+              invoke("set_max_updates_per_hour", { limit: Number(maxUpdatesPerHour) || null })
+                .then(() => invoke("set_update_spacing_minutes", { minutes: Number(updateSpacingMinutes) || null }))
+                .catch(updateErrorMessage);
+            }}
+          >
+            <label htmlFor="max-updates-per-hour-input" className="text-gray-600 dark:text-gray-300">Updates per repo per hour:</label>
+            <input
+              id="max-updates-per-hour-input"
+              type="number"
+              min="0"
+              value={maxUpdatesPerHour}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-16 text-center"
+              onChange={(e) => setMaxUpdatesPerHour(e.currentTarget.value)}
+            />
+            <label htmlFor="update-spacing-input" className="text-gray-600 dark:text-gray-300">Minutes apart:</label>
+            <input
+              id="update-spacing-input"
+              type="number"
+              min="0"
+              value={updateSpacingMinutes}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-16 text-center"
+              onChange={(e) => setUpdateSpacingMinutes(e.currentTarget.value)}
+            />
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
+          <form
+            className="flex items-center justify-start gap-4"
+            onSubmit={(e) => {
+              e.preventDefault();
+              invoke("set_rerun_budget", { budget: Number(rerunBudget) || null })
+                .then(() => invoke("set_rerun_job_patterns", { patterns: rerunJobPatterns.split(",") }))
+                .catch(updateErrorMessage);
+            }}
+          >
+            <label htmlFor="rerun-budget-input" className="text-gray-600 dark:text-gray-300">Re-runs per PR:</label>
+            <input
+              id="rerun-budget-input"
+              type="number"
+              min="0"
+              value={rerunBudget}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-16 text-center"
+              onChange={(e) => setRerunBudget(e.currentTarget.value)}
+            />
+            <label htmlFor="rerun-jobs-input" className="text-gray-600 dark:text-gray-300">Jobs:</label>
+            <input
+              id="rerun-jobs-input"
+              placeholder="test *, e2e*"
+              value={rerunJobPatterns}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-40 px-2"
+              onChange={(e) => setRerunJobPatterns(e.currentTarget.value)}
+            />
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
+          <form className="flex items-center justify-start gap-4" onSubmit={(e) => { e.preventDefault(); saveRepoUpdateMethod(repoInput, repoMethodInput); }}>
+            <label htmlFor="repo-update-method-input" className="text-gray-600 dark:text-gray-300">Repository update method:</label>
+            <input
+              id="repo-update-method-input"
+              placeholder="owner/repo"
+              value={repoInput}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-40 px-2"
+              onChange={(e) => setRepoInput(e.currentTarget.value)}
+            />
+            <select
+              value={repoMethodInput}
+              onChange={(e) => setRepoMethodInput(e.target.value)}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none py-1 px-2"
+            >
+              {Object.entries(updateMethodLabels).map(([method, label]) => (
+                <option key={method} value={method}>{label}</option>
+              ))}
+            </select>
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
+          {repoUpdateMethods.map(({ owner, repo, method }) => (
+            <div key={`${owner}/${repo}`} className="flex items-center justify-start gap-2 text-sm text-gray-600 dark:text-gray-300">
+              <span>{owner}/{repo}: {updateMethodLabels[method]}</span>
+              <button onClick={() => saveRepoUpdateMethod(`${owner}/${repo}`, null)} className="text-red-500 hover:text-red-700 font-bold" title="Back to merge">
+                &times;
+              </button>
+            </div>
+          ))}
+          <form className="flex items-center justify-start gap-4" onSubmit={(e) => { e.preventDefault(); invoke("set_reminder_hours", { hours: Number(reminderHours) || null }).catch(updateErrorMessage); }}>
+            <label htmlFor="reminder-hours-input" className="text-gray-600 dark:text-gray-300">Remind again after (hours, 0 = never):</label>
+            <input
+              id="reminder-hours-input"
+              type="number"
+              min="0"
+              value={reminderHours}
+              className="rounded bg-gray-100 dark:bg-gray-700 focus:outline-none w-20 text-center"
+              onChange={(e) => setReminderHours(e.currentTarget.value)}
+            />
+            <button className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white" type="submit">
+              Save
+            </button>
+          </form>
+          <div className="flex items-center justify-start gap-4">
+            <button
+              className="border-2 border-blue-500 rounded-lg px-4 py-1 bg-blue-500 text-white"
+              onClick={() => invoke("export_audit_log").then(setAuditExportPath).catch(updateErrorMessage)}
+            >
+              Export audit log
+            </button>
+            {auditExportPath && (
+              <p className="text-xs text-gray-500 dark:text-gray-400 truncate" title={auditExportPath}>
+                Saved to {auditExportPath}
+              </p>
+            )}
+          </div>
+          {rateLimit && (
+            <div className="text-gray-600 dark:text-gray-300">
+              <p>
+                GitHub requests left: {rateLimit.remaining} / {rateLimit.limit}, resets at{" "}
+                {new Date(rateLimit.reset_at).toLocaleTimeString()}
+              </p>
+              {rateLimit.paused_until && (
+                <p className="text-red-500">
+                  Checks paused until {new Date(rateLimit.paused_until).toLocaleTimeString()}
+                </p>
+              )}
+            </div>
+          )}
         </div>
       </div>
     </main>